@@ -0,0 +1,252 @@
+// Compares a sequential walkdir scan with the parallel walker on a generated tree, and how long the
+// listings take to reach the window through the scan channel:
+//   cargo bench --bench scan -- [DIRS_PER_LEVEL] [DEPTH] [FILES_PER_DIR]
+// Its unit tests are compiled in as well with `--all-targets`, but only run from the main crate
+#[allow(dead_code)]
+#[path = "../src/parallel_walk.rs"]
+mod parallel_walk;
+#[path = "../src/scan_queue.rs"]
+mod scan_queue;
+
+use parallel_walk::{Visitor, WalkOptions};
+use scan_queue::{Drained, Throttle};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+const RUNS: usize = 5;
+// The same values the window uses
+const TICK_BUDGET: Duration = Duration::from_millis(8);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
+// What the window handled per tick before
+const EVENTS_PER_TICK: usize = 64;
+
+// Stands in for `ScanEvent`
+enum Event {
+    Progress,
+    Visit,
+    Dir(Vec<PathBuf>),
+}
+
+#[derive(Clone, Copy)]
+enum Delivery {
+    // A fixed number of events per tick and a progress event for every directory
+    FixedCount,
+    // Events until the time budget is used up and throttled progress
+    TimeBudget,
+}
+
+// Keeps every directory and the `.png` files, with the depth as state
+struct PngVisitor;
+
+impl Visitor for PngVisitor {
+    type State = usize;
+
+    fn keep_dir(&self, _parent: &usize, _dir: &Path) -> bool {
+        true
+    }
+
+    fn enter(&self, parent: &usize, _dir: &Path) -> usize {
+        parent + 1
+    }
+
+    fn keep_file(&self, _state: &usize, path: &Path) -> bool {
+        is_png(path)
+    }
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "png")
+}
+
+fn create_tree(dir: &Path, dirs_per_level: usize, depth: usize, files_per_dir: usize) -> usize {
+    fs::create_dir_all(dir).expect("Failed to create directory");
+    for i in 0..files_per_dir {
+        // Every other file is not an image, so the filter has something to do
+        let ext = if i % 2 == 0 { "png" } else { "txt" };
+        fs::write(dir.join(format!("file{i}.{ext}")), b"").expect("Failed to create file");
+    }
+
+    let mut dirs = 1;
+    if depth > 0 {
+        for i in 0..dirs_per_level {
+            dirs += create_tree(
+                &dir.join(format!("dir{i}")),
+                dirs_per_level,
+                depth - 1,
+                files_per_dir,
+            );
+        }
+    }
+    dirs
+}
+
+// Groups files by parent like the scanner did before, in walk order
+fn scan_sequential(root: &Path, max_depth: usize) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let mut order = Vec::new();
+    let mut files: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+
+    let walker = WalkDir::new(root).max_depth(max_depth + 1).sort_by(|a, b| {
+        a.file_type()
+            .is_dir()
+            .cmp(&b.file_type().is_dir())
+            .then_with(|| a.file_name().cmp(b.file_name()))
+    });
+    for entry in walker.into_iter().flatten() {
+        if entry.file_type().is_dir() {
+            if entry.depth() <= max_depth {
+                order.push(entry.path().to_path_buf());
+            }
+        } else if is_png(entry.path())
+            && let Some(parent) = entry.path().parent()
+        {
+            files
+                .entry(parent.to_path_buf())
+                .or_default()
+                .push(entry.into_path());
+        }
+    }
+
+    order
+        .into_iter()
+        .map(|dir| {
+            let files = files.remove(&dir).unwrap_or_default();
+            (dir, files)
+        })
+        .collect()
+}
+
+fn scan_parallel(root: &Path, max_depth: usize) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let options = WalkOptions {
+        max_depth,
+        ..WalkOptions::default()
+    };
+    parallel_walk::walk(
+        root,
+        0,
+        PngVisitor,
+        options,
+        Arc::new(AtomicBool::new(false)),
+    )
+    .map(|listing| {
+        assert!(listing.errors.is_empty(), "{:?}", listing.errors);
+        assert!(*listing.state <= max_depth);
+        (listing.path, listing.files)
+    })
+    .collect()
+}
+
+// Walks in the background like the scan thread and takes the events off the channel like the main loop
+fn scan_to_ui(root: &Path, max_depth: usize, delivery: Delivery) -> usize {
+    let (tx, rx) = mpsc::channel();
+    let root = root.to_path_buf();
+    let scanner = thread::spawn(move || {
+        let options = WalkOptions {
+            max_depth,
+            ..WalkOptions::default()
+        };
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut progress = Throttle::new(PROGRESS_INTERVAL);
+        for listing in parallel_walk::walk(&root, 0, PngVisitor, options, cancel) {
+            let _ = tx.send(Event::Visit);
+            if !listing.files.is_empty() {
+                let _ = tx.send(Event::Dir(listing.files));
+            }
+            if matches!(delivery, Delivery::FixedCount) || progress.ready() {
+                let _ = tx.send(Event::Progress);
+            }
+        }
+        let _ = tx.send(Event::Progress);
+    });
+
+    let mut found = 0;
+    let mut handle = |event| {
+        if let Event::Dir(files) = event {
+            found += files.len();
+        }
+    };
+    match delivery {
+        Delivery::FixedCount => 'scan: loop {
+            for _ in 0..EVENTS_PER_TICK {
+                match rx.try_recv() {
+                    Ok(event) => handle(event),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => break 'scan,
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        },
+        Delivery::TimeBudget => loop {
+            match scan_queue::drain(&rx, TICK_BUDGET, &mut handle) {
+                Drained::Finished => break,
+                Drained::OutOfTime => {}
+                Drained::Empty => thread::sleep(POLL_INTERVAL),
+            }
+        },
+    }
+
+    scanner.join().expect("Scanner panicked");
+    found
+}
+
+fn best_of(runs: usize, mut scan: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut found = 0;
+    for _ in 0..runs {
+        let start = Instant::now();
+        found = scan();
+        best = best.min(start.elapsed());
+    }
+    (best, found)
+}
+
+fn main() {
+    // `cargo bench` passes flags like `--bench` along
+    let args: Vec<usize> = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .collect();
+    let dirs_per_level = args.first().copied().unwrap_or(8);
+    let depth = args.get(1).copied().unwrap_or(4);
+    let files_per_dir = args.get(2).copied().unwrap_or(40);
+
+    let root = std::env::temp_dir().join(format!("gridx2-bench-{}", std::process::id()));
+    let dirs = create_tree(&root, dirs_per_level, depth, files_per_dir);
+    println!("{dirs} directories with {files_per_dir} files each, {RUNS} runs, best time:");
+
+    let sequential = scan_sequential(&root, depth);
+    let parallel = scan_parallel(&root, depth);
+    assert_eq!(
+        sequential, parallel,
+        "Both scans should report the same listing"
+    );
+
+    let count = |listing: Vec<(PathBuf, Vec<PathBuf>)>| listing.iter().map(|(_, f)| f.len()).sum();
+    let (sequential_time, sequential_found) =
+        best_of(RUNS, || count(scan_sequential(&root, depth)));
+    let (parallel_time, parallel_found) = best_of(RUNS, || count(scan_parallel(&root, depth)));
+    let (budget_time, budget_found) =
+        best_of(RUNS, || scan_to_ui(&root, depth, Delivery::TimeBudget));
+    // Slow enough that one run says it all
+    let (fixed_time, fixed_found) = best_of(1, || scan_to_ui(&root, depth, Delivery::FixedCount));
+
+    println!("  walkdir:  {sequential_time:>10.2?} ({sequential_found} images)");
+    println!("  parallel: {parallel_time:>10.2?} ({parallel_found} images)");
+    println!(
+        "  speedup:  {:.2}x",
+        sequential_time.as_secs_f64() / parallel_time.as_secs_f64()
+    );
+    println!("Scan to window:");
+    println!("  {EVENTS_PER_TICK} events per tick: {fixed_time:>10.2?} ({fixed_found} images)");
+    println!("  {TICK_BUDGET:?} per tick:      {budget_time:>10.2?} ({budget_found} images)");
+
+    if let Err(e) = fs::remove_dir_all(&root) {
+        eprintln!("Failed to remove {}: {e}", root.display());
+    }
+}
@@ -1,47 +1,165 @@
 use gtk4 as gtk;
-use gtk4::prelude::{BoxExt, ObjectExt, WidgetExt};
-use gtk4::{Expander, FlowBox, Label, ProgressBar};
+use gtk4::prelude::{BoxExt, CastNone, IsA, ListModelExt, ObjectExt, SelectionModelExt, WidgetExt};
+use gtk4::{Expander, GridView, Label, ListItemFactory, MultiSelection, ProgressBar, gio};
+use std::cell::Cell;
+use std::fs;
+use std::rc::Rc;
+use std::time::SystemTime;
 
 use crate::APP_CONFIG;
+use crate::folder_config::{FolderSettings, OpenCommand};
+use crate::image_item::ImageItem;
+
+// Each section scrolls on its own so the grid only has to realize the tiles that are visible
+const GRID_MAX_HEIGHT: i32 = 640;
 
 pub struct AccordionWidget {
     pub widget: gtk::Box,
     pub expander: Expander,
-    pub flow_box: FlowBox,
-    pub progress_bar: ProgressBar,
+    pub scrolled_window: gtk::ScrolledWindow,
+    pub store: gio::ListStore,
+    pub selection: MultiSelection,
+    pub grid_view: GridView,
+    pub progress: LoadProgress,
+    pub snapshot: Option<SectionSnapshot>,
+}
+
+// What a populated section was built from, to tell whether it can be shown again as it is
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionSnapshot {
+    pub modified: Option<SystemTime>,
+    pub thumbnail_size: u32,
+    pub open_command: OpenCommand,
+}
+
+impl SectionSnapshot {
+    pub fn new(dir_path: &str, settings: &FolderSettings) -> Self {
+        Self {
+            modified: fs::metadata(dir_path).and_then(|m| m.modified()).ok(),
+            thumbnail_size: settings.thumbnail_size,
+            open_command: settings.open_command.clone(),
+        }
+    }
+}
+
+// Tracks the thumbnails of one section that are still being decoded
+#[derive(Clone)]
+pub struct LoadProgress {
+    progress_bar: ProgressBar,
+    requested: Rc<Cell<u32>>,
+    completed: Rc<Cell<u32>>,
+}
+
+impl LoadProgress {
+    fn new(progress_bar: ProgressBar) -> Self {
+        Self {
+            progress_bar,
+            requested: Rc::new(Cell::new(0)),
+            completed: Rc::new(Cell::new(0)),
+        }
+    }
+
+    pub fn start(&self) {
+        if self.requested.get() == self.completed.get() {
+            self.requested.set(0);
+            self.completed.set(0);
+        }
+        self.requested.set(self.requested.get() + 1);
+        self.update();
+    }
+
+    pub fn is_same(&self, other: &LoadProgress) -> bool {
+        Rc::ptr_eq(&self.requested, &other.requested)
+    }
+
+    pub fn finish(&self) {
+        self.completed.set(self.completed.get() + 1);
+        self.update();
+    }
+
+    fn update(&self) {
+        let requested = self.requested.get();
+        let completed = self.completed.get();
+
+        if completed >= requested {
+            self.progress_bar.set_visible(false);
+            return;
+        }
+
+        self.progress_bar
+            .set_fraction(completed as f64 / requested as f64);
+        self.progress_bar.set_visible(true);
+    }
 }
 
 impl AccordionWidget {
     pub fn new(title: &str) -> Self {
         let expander = Self::create_expander(title);
-        let flow_box = Self::create_flow_box();
+        let store = gio::ListStore::new::<ImageItem>();
+        let selection = MultiSelection::new(Some(store.clone()));
+        let grid_view = Self::create_grid_view(&selection);
 
-        expander.set_child(Some(&flow_box));
+        let scrolled_window = gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .vscrollbar_policy(gtk::PolicyType::Automatic)
+            .propagate_natural_height(true)
+            .max_content_height(GRID_MAX_HEIGHT)
+            .child(&grid_view)
+            .build();
+
+        expander.set_child(Some(&scrolled_window));
 
         let vbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
         vbox.add_css_class("expander-box");
 
-        if let Ok(app_config) = APP_CONFIG.read() {
-            match app_config.dark_mode.unwrap_or(true) {
-                true => vbox.add_css_class("dark-mode"),
-                false => vbox.add_css_class("light-mode"),
-            }
-        }
-
         let progress_bar = ProgressBar::new();
         progress_bar.set_visible(false);
 
         vbox.append(&progress_bar);
         vbox.append(&expander);
 
-        Self {
+        let accordion_widget = Self {
             widget: vbox,
             expander,
-            flow_box,
-            progress_bar,
+            scrolled_window,
+            store,
+            selection,
+            grid_view,
+            progress: LoadProgress::new(progress_bar),
+            snapshot: None,
+        };
+
+        if let Ok(app_config) = APP_CONFIG.read() {
+            accordion_widget.set_dark_mode(app_config.is_dark_mode());
+        }
+
+        accordion_widget
+    }
+
+    pub fn set_dark_mode(&self, dark_mode: bool) {
+        let (add, remove) = match dark_mode {
+            true => ("dark-mode", "light-mode"),
+            false => ("light-mode", "dark-mode"),
+        };
+        self.widget.remove_css_class(remove);
+        self.widget.add_css_class(add);
+    }
+
+    pub fn set_factory(&self, factory: &impl IsA<ListItemFactory>) {
+        self.grid_view.set_factory(Some(factory));
+    }
+
+    // Shows the settings the section was built with when hovering its title
+    pub fn set_details(&self, details: &str) {
+        if let Some(label) = self.expander.label_widget() {
+            label.set_tooltip_text(Some(details));
         }
     }
 
+    pub fn selected_paths(&self) -> Vec<String> {
+        selected_paths(&self.selection)
+    }
+
     pub fn connect_expanded<F: Fn(bool) + 'static>(&self, callback: F) {
         self.expander
             .connect_notify_local(Some("expanded"), move |expander, _| {
@@ -50,19 +168,15 @@ impl AccordionWidget {
             });
     }
 
-    fn create_flow_box() -> FlowBox {
-        let flow_box = FlowBox::new();
+    fn create_grid_view(selection: &MultiSelection) -> GridView {
+        let grid_view = GridView::new(Some(selection.clone()), None::<ListItemFactory>);
 
-        flow_box.set_valign(gtk::Align::Start);
-        flow_box.set_max_children_per_line(30);
-        flow_box.set_selection_mode(gtk::SelectionMode::None);
-        flow_box.set_homogeneous(false);
-        flow_box.set_min_children_per_line(1);
+        grid_view.set_valign(gtk::Align::Start);
+        grid_view.set_max_columns(30);
+        grid_view.set_min_columns(1);
+        grid_view.add_css_class("image-grid");
 
-        flow_box.set_row_spacing(8);
-        flow_box.set_column_spacing(8);
-
-        flow_box
+        grid_view
     }
 
     fn create_expander(title: &str) -> Expander {
@@ -77,3 +191,12 @@ impl AccordionWidget {
         expander
     }
 }
+
+// Paths of the selected thumbnails of one section, in the order they are shown
+pub fn selected_paths(selection: &impl IsA<gtk::SelectionModel>) -> Vec<String> {
+    let selected = selection.selection();
+    (0..selected.size() as u32)
+        .filter_map(|n| selection.item(selected.nth(n)).and_downcast::<ImageItem>())
+        .map(|item| item.path())
+        .collect()
+}
@@ -0,0 +1,104 @@
+use crate::command_template;
+use gtk4 as gtk;
+use gtk4::glib;
+use gtk4::prelude::{BoxExt, ButtonExt, TextBufferExt, TextViewExt, WidgetExt};
+use std::process::Output;
+
+const OUTPUT_HEIGHT: i32 = 160;
+
+// Collapsible panel above the status bar with what custom actions printed and how they exited
+#[derive(Clone)]
+pub struct ActionOutput {
+    pub widget: gtk::Expander,
+    text_view: gtk::TextView,
+    buffer: gtk::TextBuffer,
+    end_mark: gtk::TextMark,
+}
+
+impl ActionOutput {
+    pub fn new() -> Self {
+        let buffer = gtk::TextBuffer::new(None);
+        let end_mark = buffer.create_mark(None, &buffer.end_iter(), false);
+
+        let text_view = gtk::TextView::with_buffer(&buffer);
+        text_view.set_editable(false);
+        text_view.set_cursor_visible(false);
+        text_view.set_monospace(true);
+        text_view.set_wrap_mode(gtk::WrapMode::WordChar);
+
+        let scrolled_window = gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .vscrollbar_policy(gtk::PolicyType::Automatic)
+            .min_content_height(OUTPUT_HEIGHT)
+            .child(&text_view)
+            .build();
+
+        let clear_button = gtk::Button::with_label("Clear");
+        clear_button.set_halign(gtk::Align::End);
+        clear_button.connect_clicked(glib::clone!(
+            #[weak]
+            buffer,
+            move |_| buffer.set_text("")
+        ));
+
+        let vbox = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        vbox.append(&scrolled_window);
+        vbox.append(&clear_button);
+
+        let widget = gtk::Expander::new(Some("Action output"));
+        widget.add_css_class("action-output");
+        widget.set_child(Some(&vbox));
+        // Hidden until the first action runs
+        widget.set_visible(false);
+
+        Self {
+            widget,
+            text_view,
+            buffer,
+            end_mark,
+        }
+    }
+
+    pub fn start(&self, name: &str, command: &[String]) {
+        self.append(&format!(
+            "[{name}] $ {}\n",
+            command_template::to_text(command)
+        ));
+    }
+
+    pub fn finish(&self, name: &str, result: &anyhow::Result<Output>) {
+        match result {
+            Ok(output) => {
+                self.append(&String::from_utf8_lossy(&output.stdout));
+                self.append(&String::from_utf8_lossy(&output.stderr));
+                self.append(&format!("[{name}] {}\n\n", output.status));
+                if !output.status.success() {
+                    self.widget.set_expanded(true);
+                }
+            }
+            Err(e) => {
+                self.append(&format!("[{name}] failed to run: {e}\n\n"));
+                self.widget.set_expanded(true);
+            }
+        }
+    }
+
+    pub fn append_note(&self, text: &str) {
+        self.append(&format!("{text}\n\n"));
+    }
+
+    fn append(&self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if !self.widget.is_visible() {
+            self.widget.set_visible(true);
+            self.widget.set_expanded(true);
+        }
+
+        self.buffer.insert(&mut self.buffer.end_iter(), text);
+        self.buffer
+            .move_mark(&self.end_mark, &self.buffer.end_iter());
+        self.text_view.scroll_mark_onscreen(&self.end_mark);
+    }
+}
@@ -1,60 +1,274 @@
+use crate::custom_action::CustomAction;
+use crate::texture_cache::parse_size;
+use anyhow::anyhow;
 use gtk4 as gtk;
 use gtk4::glib::object::ObjectExt;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+// Bumped whenever the layout of the file changes, with a matching step in MIGRATIONS
+pub const CONFIG_VERSION: u32 = 1;
+
+// What the settings window offers. Sizes above 512 px, the largest shared thumbnail, decode the full image
+pub const MAX_DEPTHS: RangeInclusive<u32> = 0..=64;
+pub const THUMBNAIL_SIZES: RangeInclusive<u32> = 32..=1024;
+
+// Like the limit of the kernel, so a link loop fails instead of hanging
+const MAX_LINKS: usize = 40;
+
+// Problems found while reading the config, before there is a window to show them in
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Each step upgrades a table of version `i` to version `i + 1`
+const MIGRATIONS: [fn(&mut toml::Table); CONFIG_VERSION as usize] = [
+    // Unversioned files have the same layout, the fields they lack take their defaults
+    |_| {},
+];
+
+// Fields missing from the file take the value of `AppConfig::default()`
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct AppConfig {
+    // Files from before versioning have no version, which reads as 0
+    #[serde(default)]
+    pub config_version: u32,
+    // How many levels of subfolders are scanned, 0 shows the opened folder only
     pub max_depth: u32,
     pub thumbnail_size: u32,
     pub open_command: Vec<String>,
     pub dark_mode: Option<bool>,
+    pub cache_budget: String, // e.g. "512 MiB", the decoded size of thumbnails kept in memory
+    // Lowercase extension to the desktop file id of the application opening it, e.g. "exr" = "org.gimp.GIMP.desktop"
+    pub open_with: BTreeMap<String, String>,
+    // `[[actions]]` tables, shown in the Actions menu and the context menu of thumbnails
+    pub actions: Vec<CustomAction>,
+    // Lowercase extensions treated as images, unset means every format a decoder is compiled in for
+    pub image_extensions: Option<Vec<String>>,
+    // Also look at the first bytes of files whose extension is unknown or missing
+    pub sniff_content: bool,
+    // Gitignore-style patterns relative to the opened folder, on top of .gitignore, .ignore and .gridx2ignore files
+    pub ignore_patterns: Vec<String>,
+    // Whether files and folders starting with a dot are shown
+    pub show_hidden: bool,
+    // Descend into symlinked folders. Links back to a parent are skipped
+    pub follow_symlinks: bool,
+    // Stay on the file system of the opened folder, e.g. to not scan mounted network shares
+    pub same_file_system: bool,
+    // Keys written by a newer release, kept so saving here does not drop them
+    #[serde(flatten)]
+    pub unknown: toml::Table,
+}
+
+// Values given on the command line, which win over the file for the whole session
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConfigOverrides {
+    pub max_depth: Option<u32>,
+    pub thumbnail_size: Option<u32>,
+}
+
+impl ConfigOverrides {
+    pub const fn new() -> Self {
+        Self {
+            max_depth: None,
+            thumbnail_size: None,
+        }
+    }
+
+    // Values changed in the settings since replace the ones given on the command line
+    pub fn forget_changed(&mut self, before: &AppConfig, after: &AppConfig) {
+        if before.max_depth != after.max_depth {
+            self.max_depth = None;
+        }
+        if before.thumbnail_size != after.thumbnail_size {
+            self.thumbnail_size = None;
+        }
+    }
+
+    pub fn apply(&self, config: &mut AppConfig) {
+        if let Some(max_depth) = self.max_depth {
+            config.max_depth = max_depth;
+        }
+        if let Some(thumbnail_size) = self.thumbnail_size {
+            config.thumbnail_size = thumbnail_size;
+        }
+    }
+}
+
+fn default_cache_budget() -> String {
+    "512 MiB".into()
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            config_version: CONFIG_VERSION,
             max_depth: 2,
             thumbnail_size: 200,
             open_command: vec!["xdg-open".into(), "<path>".into()], // the actual path is assigned to <path>
             dark_mode: Some(true),
+            cache_budget: default_cache_budget(),
+            open_with: BTreeMap::new(),
+            actions: Vec::new(),
+            image_extensions: None,
+            sniff_content: false,
+            ignore_patterns: vec![
+                "node_modules/".into(),
+                "__MACOSX/".into(),
+                "*.lrdata/".into(),
+            ],
+            show_hidden: false,
+            follow_symlinks: false,
+            same_file_system: false,
+            unknown: toml::Table::new(),
         }
     }
 }
 
 impl AppConfig {
     pub fn load() -> anyhow::Result<Self> {
-        let found = Self::get_exist_path()?;
+        let path = Self::config_path()?;
+        if !path.exists() {
+            Self::migrate_legacy(&path)?;
+        }
+        // First run, the file is created by the first save
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        match Self::from_content(&content) {
+            Ok(config) => Ok(config),
+            // Keep the broken file around instead of overwriting it with defaults on the next save
+            Err(e) => {
+                let backup_path = Self::back_up(&path)?;
+                Err(anyhow!(
+                    "{} could not be read and was moved to {}: {e}",
+                    path.display(),
+                    backup_path.display()
+                ))
+            }
+        }
+    }
+
+    // Reads the file again for a running instance. Returns None once it is gone.
+    // Unlike `load`, a broken file is left where it is, since it is most likely being edited
+    pub fn reload() -> anyhow::Result<Option<Self>> {
+        let path = Self::config_path()?;
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path)?;
+        Self::from_content(&content).map(Some)
+    }
 
-        let content = fs::read_to_string(found)?;
-        let mut config: Self = toml::from_str(&content)?;
+    fn from_content(content: &str) -> anyhow::Result<Self> {
+        let (config, migrated) = Self::parse(content)?;
 
-        if config.dark_mode.is_none() {
-            config.dark_mode = Some(Self::get_dark_mode());
+        if migrated && let Err(e) = config.save() {
+            warn(format!("Failed to save the upgraded config: {e}"));
         }
 
         Ok(config)
     }
 
-    pub fn save(&self) -> anyhow::Result<()> {
-        let found = Self::get_exist_path()?;
+    // Returns the config and whether it had to be upgraded from an older version
+    fn parse(content: &str) -> anyhow::Result<(Self, bool)> {
+        let mut table: toml::Table = toml::from_str(content)?;
 
-        let content = toml::to_string(self)?;
-        fs::write(found, content)?;
+        let version = match table.get("config_version") {
+            Some(value) => value
+                .as_integer()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or_else(|| anyhow!("Invalid config_version: {value}"))?,
+            None => 0,
+        };
 
-        Ok(())
+        if version > CONFIG_VERSION {
+            warn(format!(
+                "The config was written by a newer version of gridx2 (version {version}), unknown settings are kept as they are"
+            ));
+        }
+        for migration in MIGRATIONS.iter().skip(version as usize) {
+            migration(&mut table);
+        }
+        let migrated = version < CONFIG_VERSION;
+        if migrated {
+            table.insert("config_version".into(), CONFIG_VERSION.into());
+        }
+
+        let config = toml::Value::Table(table).try_into()?;
+        Ok((config, migrated))
     }
 
-    fn get_exist_path() -> anyhow::Result<PathBuf> {
-        let home_path = home::home_dir().ok_or(anyhow::anyhow!("No home directory found"))?;
-        let save_path = home_path.join(".gridx2.toml");
+    // Moves the file a link points to, so the next save writes through the link again
+    fn back_up(path: &Path) -> anyhow::Result<PathBuf> {
+        let path = &resolve_link(path)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let backup_path = path.with_file_name(format!(
+            "{}.broken-{timestamp}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+
+        fs::rename(path, &backup_path)?;
+        Ok(backup_path)
+    }
 
-        let path = save_path.canonicalize()?;
-        if path.exists() {
-            return Ok(path);
+    // Unset means following the GTK theme
+    pub fn is_dark_mode(&self) -> bool {
+        self.dark_mode.unwrap_or_else(Self::get_dark_mode)
+    }
+
+    pub fn cache_budget_bytes(&self) -> usize {
+        match parse_size(&self.cache_budget) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn(format!("Invalid cache budget, using the default: {e}"));
+                parse_size(&default_cache_budget()).unwrap_or_default()
+            }
         }
-        Err(anyhow::anyhow!("No config save directory found"))
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let content = toml::to_string(self)?;
+        write_atomic(&Self::config_path()?, &content)
+    }
+
+    // $XDG_CONFIG_HOME/gridx2/config.toml, falling back to ~/.config like the spec asks
+    pub fn config_path() -> anyhow::Result<PathBuf> {
+        let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if Path::new(&dir).is_absolute() => PathBuf::from(dir),
+            _ => home::home_dir()
+                .ok_or(anyhow!("No home directory found"))?
+                .join(".config"),
+        };
+
+        Ok(config_home.join("gridx2").join("config.toml"))
+    }
+
+    // Older releases kept the config in ~/.gridx2.toml
+    fn migrate_legacy(path: &Path) -> anyhow::Result<()> {
+        let home_path = home::home_dir().ok_or(anyhow!("No home directory found"))?;
+        let legacy_path = home_path.join(".gridx2.toml");
+        if !legacy_path.exists() {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(&legacy_path)?;
+        write_atomic(path, &content)?;
+        fs::remove_file(&legacy_path)?;
+        println!("Moved {} to {}", legacy_path.display(), path.display());
+
+        Ok(())
     }
 
     fn get_dark_mode() -> bool {
@@ -74,3 +288,120 @@ impl AppConfig {
         }
     }
 }
+
+// Writes next to the final location first, so a crash never leaves a truncated config behind.
+// A linked config, e.g. from a dotfiles repository, is written where the link points and stays a link
+fn write_atomic(path: &Path, content: &str) -> anyhow::Result<()> {
+    let path = &resolve_link(path)?;
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid config path: {path:?}"))?;
+    fs::create_dir_all(dir)?;
+
+    let tmp_path = dir.join(format!(
+        ".{}.{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = (|| -> anyhow::Result<()> {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => {
+            fs::rename(&tmp_path, path)?;
+            Ok(())
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+// Follows links one after another, since the target of a broken link does not exist yet to canonicalize
+pub fn resolve_link(path: &Path) -> anyhow::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(&path)?;
+                // Relative targets start from the folder of the link
+                path = match path.parent() {
+                    Some(dir) => dir.join(target),
+                    None => target,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+    Err(anyhow!("Too many levels of links at {}", path.display()))
+}
+
+// Queued once per cause until the window takes them
+fn warn(text: String) {
+    if let Ok(mut warnings) = WARNINGS.lock()
+        && !warnings.contains(&text)
+    {
+        warnings.push(text);
+    }
+}
+
+pub fn take_warnings() -> Vec<String> {
+    match WARNINGS.lock() {
+        Ok(mut warnings) => std::mem::take(&mut *warnings),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrades_files_without_a_version() {
+        let (config, migrated) = AppConfig::parse("max_depth = 3").unwrap();
+
+        assert!(migrated);
+        assert_eq!(config.config_version, CONFIG_VERSION);
+        assert_eq!(config.max_depth, 3);
+    }
+
+    #[test]
+    fn keeps_unknown_keys_of_newer_versions() {
+        let newer = CONFIG_VERSION + 1;
+        let content = format!("config_version = {newer}\nfuture_setting = \"kept\"\n");
+        let (config, migrated) = AppConfig::parse(&content).unwrap();
+
+        assert!(!migrated);
+        assert_eq!(config.config_version, newer);
+
+        let saved: toml::Table = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(saved["future_setting"].as_str(), Some("kept"));
+        assert_eq!(saved["config_version"].as_integer(), Some(newer.into()));
+    }
+
+    #[test]
+    fn fills_missing_fields_with_defaults() {
+        let content = format!("config_version = {CONFIG_VERSION}\nthumbnail_size = 300\n");
+        let (config, _) = AppConfig::parse(&content).unwrap();
+        let defaults = AppConfig::default();
+
+        assert_eq!(config.thumbnail_size, 300);
+        assert_eq!(config.max_depth, defaults.max_depth);
+        assert_eq!(config.open_command, defaults.open_command);
+        assert_eq!(config.cache_budget, defaults.cache_budget);
+        assert_eq!(config.ignore_patterns, defaults.ignore_patterns);
+        assert!(config.unknown.is_empty());
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        assert!(AppConfig::parse("max_depth = \"deep\"").is_err());
+        assert!(AppConfig::parse("config_version = \"1\"").is_err());
+    }
+}
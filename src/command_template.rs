@@ -0,0 +1,178 @@
+use anyhow::anyhow;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+// Placeholders usable anywhere inside an argument. A bare `<paths>` argument becomes one argument per file
+pub const PLACEHOLDERS: [&str; 6] = ["<path>", "<dir>", "<name>", "<stem>", "<ext>", "<paths>"];
+
+// Splits what was typed into the settings with shell quoting rules
+pub fn parse(text: &str) -> anyhow::Result<Vec<String>> {
+    let command = shell_words::split(text).map_err(|e| anyhow!("Invalid command: {e}"))?;
+    if command.is_empty() {
+        return Err(anyhow!("The command must not be empty"));
+    }
+    Ok(command)
+}
+
+pub fn to_text(command: &[String]) -> String {
+    shell_words::join(command)
+}
+
+// Fills in the placeholders for `paths`. Single-file placeholders refer to the first path.
+// A command without any placeholder gets the paths appended, like a desktop launcher would
+pub fn expand(template: &[String], paths: &[String]) -> anyhow::Result<Vec<String>> {
+    let first = paths
+        .first()
+        .ok_or_else(|| anyhow!("No file to run the command on"))?;
+    let path = Path::new(first);
+    let lossy = |part: Option<&std::ffi::OsStr>| {
+        part.map(|part| part.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+
+    let values = [
+        ("<paths>", paths.join(" ")),
+        ("<path>", first.clone()),
+        ("<dir>", lossy(path.parent().map(Path::as_os_str))),
+        ("<name>", lossy(path.file_name())),
+        ("<stem>", lossy(path.file_stem())),
+        ("<ext>", lossy(path.extension())),
+    ];
+
+    let mut command = Vec::new();
+    for arg in template {
+        if arg == "<paths>" {
+            command.extend(paths.iter().cloned());
+        } else {
+            command.push(substitute(arg, &values));
+        }
+    }
+
+    let has_placeholder = template.iter().any(|arg| {
+        PLACEHOLDERS
+            .iter()
+            .any(|placeholder| arg.contains(placeholder))
+    });
+    if !has_placeholder {
+        command.extend(paths.iter().cloned());
+    }
+
+    if command.is_empty() {
+        return Err(anyhow!("The command is empty"));
+    }
+    Ok(command)
+}
+
+pub fn spawn(command: &[String]) -> anyhow::Result<()> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("The command is empty"))?;
+
+    Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .stdin(Stdio::null())
+        .spawn()?;
+
+    Ok(())
+}
+
+// Waits for the command and captures what it printed
+pub fn output(command: &[String]) -> anyhow::Result<Output> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("The command is empty"))?;
+
+    Ok(Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?)
+}
+
+// Replaces in one pass, so a file name that happens to contain a placeholder is left as it is
+fn substitute(arg: &str, values: &[(&str, String)]) -> String {
+    let mut result = String::new();
+    let mut rest = arg;
+
+    'search: while let Some(start) = rest.find('<') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        for (placeholder, value) in values {
+            if let Some(after) = rest.strip_prefix(placeholder) {
+                result.push_str(value);
+                rest = after;
+                continue 'search;
+            }
+        }
+
+        result.push('<');
+        rest = &rest[1..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn expands_paths_to_one_argument_per_file() {
+        let template = strings(&["gimp", "--new-instance", "<paths>"]);
+        let paths = strings(&["/pics/a.png", "/pics/b.png"]);
+
+        assert_eq!(
+            expand(&template, &paths).unwrap(),
+            ["gimp", "--new-instance", "/pics/a.png", "/pics/b.png"]
+        );
+    }
+
+    #[test]
+    fn appends_paths_without_a_placeholder() {
+        let template = strings(&["feh", "--fullscreen"]);
+
+        assert_eq!(
+            expand(&template, &strings(&["/pics/a.png"])).unwrap(),
+            ["feh", "--fullscreen", "/pics/a.png"]
+        );
+        assert_eq!(
+            expand(&template, &strings(&["/pics/a.png", "/pics/b.png"])).unwrap(),
+            ["feh", "--fullscreen", "/pics/a.png", "/pics/b.png"]
+        );
+    }
+
+    #[test]
+    fn keeps_paths_with_spaces_in_one_argument() {
+        let template = parse("convert '<path>' -resize 50% \"<dir>/small <name>\"").unwrap();
+        let paths = strings(&["/my pics/a b.png", "/my pics/c d.png"]);
+
+        assert_eq!(
+            expand(&template, &paths).unwrap(),
+            [
+                "convert",
+                "/my pics/a b.png",
+                "-resize",
+                "50%",
+                "/my pics/small a b.png"
+            ]
+        );
+
+        let template = strings(&["trash-put", "<paths>"]);
+        assert_eq!(
+            expand(&template, &paths).unwrap(),
+            ["trash-put", "/my pics/a b.png", "/my pics/c d.png"]
+        );
+    }
+
+    #[test]
+    fn fails_without_paths() {
+        assert!(expand(&strings(&["feh"]), &[]).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use crate::app_config::{self, AppConfig};
+use crate::problems::Problems;
+use gtk4::gio::{FileMonitorEvent, FileMonitorFlags};
+use gtk4::prelude::{FileExt, FileMonitorExt};
+use gtk4::{gio, glib};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+// Editors and config tools often write a file in several steps, so only the last event of a burst counts
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+// Calls back whenever the config file was written, including by a rename over it
+pub struct ConfigWatcher {
+    _monitor: gio::FileMonitor,
+    // Where a linked config points, e.g. into a dotfiles repository. Edits there do not touch the link
+    _target_monitor: Rc<RefCell<Option<gio::FileMonitor>>>,
+}
+
+impl ConfigWatcher {
+    pub fn new<F: Fn() + 'static>(problems: Problems, on_change: F) -> anyhow::Result<Self> {
+        let path = AppConfig::config_path()?;
+
+        let on_change = Rc::new(on_change);
+        let flush_source: Rc<RefCell<Option<glib::SourceId>>> = Rc::new(RefCell::new(None));
+        let schedule: Rc<dyn Fn()> = Rc::new(move || {
+            if let Some(source) = flush_source.borrow_mut().take() {
+                source.remove();
+            }
+
+            let on_change = on_change.clone();
+            let flush_source_inner = flush_source.clone();
+            let source = glib::timeout_add_local_once(DEBOUNCE, move || {
+                flush_source_inner.replace(None);
+                on_change();
+            });
+            flush_source.replace(Some(source));
+        });
+
+        // A new link may point somewhere else, so the target is looked up again whenever the link changes
+        let target_monitor: Rc<RefCell<Option<gio::FileMonitor>>> = Rc::new(RefCell::new(None));
+        let watch_target = {
+            let path = path.clone();
+            let schedule = schedule.clone();
+            let target_monitor = target_monitor.clone();
+            move || {
+                let monitor = match app_config::resolve_link(&path) {
+                    Ok(target) if target != path => match watch(&target, schedule.clone()) {
+                        Ok(monitor) => Some(monitor),
+                        Err(e) => {
+                            problems.notify(&format!(
+                                "Changes to {} are not picked up: {e}",
+                                target.display()
+                            ));
+                            None
+                        }
+                    },
+                    _ => None,
+                };
+                if let Some(previous) = target_monitor.replace(monitor) {
+                    previous.cancel();
+                }
+            }
+        };
+        watch_target();
+
+        let monitor = watch(
+            &path,
+            Rc::new(move || {
+                watch_target();
+                schedule();
+            }),
+        )?;
+
+        Ok(Self {
+            _monitor: monitor,
+            _target_monitor: target_monitor,
+        })
+    }
+}
+
+fn watch(path: &Path, on_write: Rc<dyn Fn()>) -> anyhow::Result<gio::FileMonitor> {
+    let monitor = gio::File::for_path(path)
+        .monitor_file(FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)?;
+
+    monitor.connect_changed(move |_, _, _, event| {
+        if matches!(
+            event,
+            FileMonitorEvent::ChangesDoneHint
+                | FileMonitorEvent::Created
+                | FileMonitorEvent::Renamed
+                | FileMonitorEvent::MovedIn
+        ) {
+            on_write();
+        }
+    });
+
+    Ok(monitor)
+}
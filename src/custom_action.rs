@@ -0,0 +1,145 @@
+use crate::action_output::ActionOutput;
+use crate::command_template;
+use crate::problems::Problems;
+use anyhow::anyhow;
+use futures_channel::oneshot;
+use gtk4 as gtk;
+use gtk4::prelude::{GtkApplicationExt, ToVariant};
+use gtk4::{Application, gio};
+use serde::{Deserialize, Serialize};
+use std::thread;
+
+// Both take the index of the action in `AppConfig::actions`, `run-action-on` also the files to run it on
+pub const RUN_ACTION: &str = "run-action";
+pub const RUN_ACTION_ON: &str = "run-action-on";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionMode {
+    // The command runs once for every file, one after another
+    #[default]
+    PerFile,
+    // The command runs once, with `<paths>` standing for every file
+    Selection,
+}
+
+// One `[[actions]]` entry of the config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomAction {
+    pub name: String,
+    pub command: Vec<String>,
+    // In the format of gtk_accelerator_parse, e.g. "<Control><Shift>u"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keybinding: Option<String>,
+    #[serde(default)]
+    pub mode: ActionMode,
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+impl CustomAction {
+    fn commands(&self, paths: &[String]) -> anyhow::Result<Vec<Vec<String>>> {
+        match self.mode {
+            ActionMode::PerFile => paths
+                .iter()
+                .map(|path| command_template::expand(&self.command, std::slice::from_ref(path)))
+                .collect(),
+            ActionMode::Selection => Ok(vec![command_template::expand(&self.command, paths)?]),
+        }
+    }
+}
+
+// Lists the actions in `menu` and binds their keys, replacing what an earlier config set up
+pub fn install(app: &Application, menu: &gio::Menu, actions: &[CustomAction], problems: &Problems) {
+    menu.remove_all();
+
+    let prefix = format!("app.{RUN_ACTION}(");
+    for name in app.list_action_descriptions() {
+        if name.starts_with(&prefix) {
+            app.set_accels_for_action(&name, &[]);
+        }
+    }
+
+    for (index, action) in actions.iter().enumerate() {
+        let target = (index as u32).to_variant();
+        let detailed_name =
+            gio::Action::print_detailed_name(&format!("app.{RUN_ACTION}"), Some(&target));
+        menu.append(Some(&action.name), Some(&detailed_name));
+
+        if let Some(keybinding) = &action.keybinding {
+            if gtk::accelerator_parse(keybinding).is_none() {
+                problems.notify(&format!(
+                    "Invalid keybinding for action {}: {keybinding}",
+                    action.name
+                ));
+                continue;
+            }
+            app.set_accels_for_action(&detailed_name, &[keybinding.as_str()]);
+        }
+    }
+}
+
+// Entries running each action on `paths`, for the context menu of a thumbnail
+pub fn menu_section(actions: &[CustomAction], paths: &[String]) -> gio::Menu {
+    let section = gio::Menu::new();
+    for (index, action) in actions.iter().enumerate() {
+        let target = (index as u32, paths.to_vec()).to_variant();
+        let item = gio::MenuItem::new(Some(&action.name), None);
+        item.set_action_and_target_value(Some(&format!("app.{RUN_ACTION_ON}")), Some(&target));
+        section.append_item(&item);
+    }
+    section
+}
+
+// Runs the commands one after another on worker threads, reporting each to `output` as it finishes
+pub async fn run(
+    action: CustomAction,
+    paths: Vec<String>,
+    output: ActionOutput,
+    parent: Option<gtk::Window>,
+) {
+    if paths.is_empty() {
+        output.append_note(&format!("{}: no images selected", action.name));
+        return;
+    }
+
+    if action.confirm {
+        let files = match paths.len() {
+            1 => "1 image".to_string(),
+            n => format!("{n} images"),
+        };
+        let dialog = gtk::AlertDialog::builder()
+            .modal(true)
+            .message(format!("Run \"{}\" on {files}?", action.name))
+            .buttons(["Cancel", "Run"])
+            .cancel_button(0)
+            .default_button(1)
+            .build();
+        if !matches!(dialog.choose_future(parent.as_ref()).await, Ok(1)) {
+            return;
+        }
+    }
+
+    let commands = match action.commands(&paths) {
+        Ok(commands) => commands,
+        Err(e) => {
+            output.append_note(&format!("{}: {e}", action.name));
+            return;
+        }
+    };
+
+    for command in commands {
+        output.start(&action.name, &command);
+
+        let (tx, rx) = oneshot::channel();
+        let command_inner = command.clone();
+        thread::spawn(move || {
+            let _ = tx.send(command_template::output(&command_inner));
+        });
+
+        let result = rx
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("The command thread stopped")));
+        output.finish(&action.name, &result);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::problems::Problems;
+use gtk4::gio::{FileMonitorEvent, FileMonitorFlags};
+use gtk4::prelude::{FileExt, FileMonitorExt};
+use gtk4::{gio, glib};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// Quiet period after the last event before the collected changes are applied
+const DEBOUNCE: Duration = Duration::from_millis(300);
+// Longest a burst is held back, so a folder that is written to all the time still updates
+const MAX_DELAY: Duration = Duration::from_millis(1500);
+// Every watch holds an inotify watch and a file descriptor, which the rest of the system needs too
+const MAX_WATCHES: usize = 4096;
+
+// What changed on disk since the last flush
+#[derive(Debug, Default)]
+pub struct FsChanges {
+    // Paths whose listing may have changed. Files end up here too, since a deleted entry can not be told apart
+    pub dirs: BTreeSet<String>,
+    // Files whose contents were rewritten
+    pub modified: HashSet<String>,
+}
+
+// Watches every directory of the opened folder down to `max_depth` and reports bursts of events at once
+#[derive(Clone)]
+pub struct DirWatcher {
+    root: Rc<RefCell<PathBuf>>,
+    max_depth: Rc<Cell<u32>>,
+    monitors: Rc<RefCell<HashMap<String, gio::FileMonitor>>>,
+    changes: Rc<RefCell<FsChanges>>,
+    flush_source: Rc<RefCell<Option<glib::SourceId>>>,
+    // When the first event not flushed yet came in
+    burst_start: Rc<Cell<Option<Instant>>>,
+    on_changes: Rc<RefCell<Option<Rc<dyn Fn(FsChanges)>>>>,
+    problems: Problems,
+    // Whether the user was already told that some folders are not watched
+    limit_reported: Rc<Cell<bool>>,
+}
+
+impl DirWatcher {
+    pub fn new(problems: Problems) -> Self {
+        Self {
+            root: Rc::new(RefCell::new(PathBuf::new())),
+            max_depth: Rc::new(Cell::new(0)),
+            monitors: Rc::new(RefCell::new(HashMap::new())),
+            changes: Rc::new(RefCell::new(FsChanges::default())),
+            flush_source: Rc::new(RefCell::new(None)),
+            burst_start: Rc::new(Cell::new(None)),
+            on_changes: Rc::new(RefCell::new(None)),
+            problems,
+            limit_reported: Rc::new(Cell::new(false)),
+        }
+    }
+
+    pub fn connect_changes<F: Fn(FsChanges) + 'static>(&self, callback: F) {
+        self.on_changes.replace(Some(Rc::new(callback)));
+    }
+
+    // Drops every watch of the previous folder
+    pub fn reset(&self, root: &str, max_depth: u32) {
+        for monitor in self
+            .monitors
+            .borrow_mut()
+            .drain()
+            .map(|(_, monitor)| monitor)
+        {
+            monitor.cancel();
+        }
+        if let Some(source) = self.flush_source.borrow_mut().take() {
+            source.remove();
+        }
+        self.changes.replace(FsChanges::default());
+        self.burst_start.set(None);
+        self.limit_reported.set(false);
+
+        self.root.replace(PathBuf::from(root));
+        self.max_depth.set(max_depth);
+    }
+
+    // Only directories whose images can be shown are watched, deeper ones are found through their parent
+    pub fn is_within_depth(&self, dir: &str) -> bool {
+        Path::new(dir)
+            .strip_prefix(&*self.root.borrow())
+            .is_ok_and(|relative| relative.components().count() <= self.max_depth.get() as usize)
+    }
+
+    pub fn is_watched(&self, dir: &str) -> bool {
+        self.monitors.borrow().contains_key(dir)
+    }
+
+    pub fn watch(&self, dir: &str) {
+        if self.is_watched(dir) || !self.is_within_depth(dir) {
+            return;
+        }
+        if self.monitors.borrow().len() >= MAX_WATCHES {
+            self.report_limit(&format!(
+                "Only {MAX_WATCHES} folders are watched for changes"
+            ));
+            return;
+        }
+
+        let monitor = match gio::File::for_path(dir)
+            .monitor_directory(FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)
+        {
+            Ok(monitor) => monitor,
+            Err(e)
+                if e.matches(gio::IOErrorEnum::NoSpace)
+                    || e.matches(gio::IOErrorEnum::TooManyOpenFiles) =>
+            {
+                self.report_limit(&format!("The system can not watch more folders ({e})"));
+                return;
+            }
+            Err(e) => {
+                self.problems.notify(&format!(
+                    "Changes in {dir} are not picked up, it can not be watched: {e}"
+                ));
+                return;
+            }
+        };
+
+        let watcher = self.clone();
+        monitor.connect_changed(move |_, file, other_file, event| {
+            watcher.record(file, other_file, event);
+        });
+
+        self.monitors.borrow_mut().insert(dir.to_string(), monitor);
+    }
+
+    // Said once per opened folder, since every further folder would fail the same way
+    fn report_limit(&self, reason: &str) {
+        if self.limit_reported.replace(true) {
+            return;
+        }
+        self.problems.notify(&format!(
+            "{reason}, so changes in the rest are not picked up. Press F5 to rescan"
+        ));
+    }
+
+    // Stops watching every directory a fresh scan did not visit
+    pub fn retain(&self, dirs: &HashSet<String>) {
+        self.monitors.borrow_mut().retain(|path, monitor| {
+            if dirs.contains(path) {
+                return true;
+            }
+            monitor.cancel();
+            false
+        });
+    }
+
+    // Stops watching `dir` and everything below it
+    pub fn unwatch(&self, dir: &str) {
+        self.monitors.borrow_mut().retain(|path, monitor| {
+            if Path::new(path).starts_with(dir) {
+                monitor.cancel();
+                return false;
+            }
+            true
+        });
+    }
+
+    fn record(&self, file: &gio::File, other_file: Option<&gio::File>, event: FileMonitorEvent) {
+        let paths = match event {
+            FileMonitorEvent::Changed | FileMonitorEvent::ChangesDoneHint => {
+                if let Some(path) = file.path() {
+                    self.changes
+                        .borrow_mut()
+                        .modified
+                        .insert(path.to_string_lossy().to_string());
+                }
+                vec![file]
+            }
+            FileMonitorEvent::Created
+            | FileMonitorEvent::Deleted
+            | FileMonitorEvent::MovedIn
+            | FileMonitorEvent::MovedOut => vec![file],
+            FileMonitorEvent::Renamed | FileMonitorEvent::Moved => {
+                iter::once(file).chain(other_file).collect()
+            }
+            _ => return,
+        };
+
+        {
+            let mut changes = self.changes.borrow_mut();
+            for path in paths.into_iter().filter_map(|file| file.path()) {
+                if let Some(parent) = path.parent() {
+                    changes.dirs.insert(parent.to_string_lossy().to_string());
+                }
+                changes.dirs.insert(path.to_string_lossy().to_string());
+            }
+        }
+
+        self.schedule_flush();
+    }
+
+    // Every event pushes the flush back, so copying a whole folder is applied in one go, but never
+    // further than MAX_DELAY after the first event
+    fn schedule_flush(&self) {
+        if let Some(source) = self.flush_source.borrow_mut().take() {
+            source.remove();
+        }
+
+        let burst_start = self.burst_start.get().unwrap_or_else(Instant::now);
+        self.burst_start.set(Some(burst_start));
+        let delay = DEBOUNCE.min(MAX_DELAY.saturating_sub(burst_start.elapsed()));
+
+        let watcher = self.clone();
+        let source = glib::timeout_add_local_once(delay, move || {
+            watcher.flush_source.replace(None);
+            watcher.flush();
+        });
+        self.flush_source.replace(Some(source));
+    }
+
+    fn flush(&self) {
+        self.burst_start.set(None);
+        let changes = self.changes.take();
+        let on_changes = self.on_changes.borrow().clone();
+        if let Some(on_changes) = on_changes {
+            on_changes(changes);
+        }
+    }
+}
@@ -1,107 +1,165 @@
 use crate::APP_CONFIG;
+use crate::folder_config::FolderSettings;
 use crate::image_entry::ImageEntry;
+use crate::parallel_walk::{self, Visitor, WalkOptions};
+use crate::scan_queue::Throttle;
 use anyhow::anyhow;
-use std::path;
+use std::fs;
 use std::path::Path;
-use walkdir::WalkDir;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// The window shows progress once per tick, so more often would only fill the channel
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
+
+pub enum ScanEvent {
+    Progress { dirs: usize, images: usize },
+    // Every directory the walker enters, including the ones without images
+    Visit(String),
+    Dir(DirEntry),
+    // A directory that could not be listed, which is left out while the rest is still scanned
+    Unreadable { path: String, reason: String },
+    // A `.gridx2.toml` or ignore file that was skipped, so its folder uses the inherited settings
+    BrokenSettings { path: String, reason: String },
+}
 
 #[derive(Debug, Clone)]
 pub struct DirEntry {
     pub dir_path: String,
     pub image_entries: Vec<ImageEntry>,
+    pub settings: FolderSettings,
 }
 
 impl DirEntry {
-    fn new(dir_path: String) -> Self {
+    fn new(dir_path: String, settings: FolderSettings) -> Self {
         Self {
             dir_path,
             image_entries: Vec::new(),
+            settings,
         }
     }
 
-    pub fn search(root: &str) -> anyhow::Result<Vec<DirEntry>> {
-        let max_depth = {
+    // Lists the images directly inside one directory, in the same order the scanner reports them
+    pub fn list_images(
+        dir_path: &str,
+        settings: &FolderSettings,
+    ) -> anyhow::Result<Vec<ImageEntry>> {
+        let mut image_entries = Vec::new();
+
+        for entry in fs::read_dir(dir_path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir()
+                || !settings.is_image(&entry.path())
+                || settings.is_ignored(&entry.path(), false)
+            {
+                continue;
+            }
+
+            image_entries.push(ImageEntry {
+                image_path: entry.path().to_string_lossy().to_string(),
+                image: None,
+            });
+        }
+
+        settings.sort_images(&mut image_entries);
+
+        Ok(image_entries)
+    }
+
+    // Reports every directory in walk order once its images are known. Stops early once `cancel` is set
+    pub fn search<F: FnMut(ScanEvent)>(
+        root: &str,
+        cancel: &Arc<AtomicBool>,
+        mut on_event: F,
+    ) -> anyhow::Result<()> {
+        let options = {
             let app_config = APP_CONFIG
                 .read()
                 .map_err(|_| anyhow!("Failed to lock app config"))?;
-            app_config.max_depth
+            WalkOptions {
+                max_depth: app_config.max_depth as usize,
+                follow_links: app_config.follow_symlinks,
+                same_file_system: app_config.same_file_system,
+            }
         };
+        let root_settings = FolderSettings::root(Path::new(root))?;
+
+        let mut dirs = 0;
+        let mut images = 0;
+        let mut progress = Throttle::new(PROGRESS_INTERVAL);
+
+        let walk = parallel_walk::walk(
+            Path::new(root),
+            root_settings,
+            ScanVisitor,
+            options,
+            cancel.clone(),
+        );
+        for listing in walk {
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(());
+            }
 
-        let mut entries: Vec<DirEntry> = Vec::new();
-        let max_depth = count_depth(to_absolute(root)?) + max_depth;
-
-        let walker = WalkDir::new(root).into_iter();
-
-        let should_process = |entry: &walkdir::DirEntry| -> bool {
-            if let Ok(absolute) = to_absolute(entry.path()) {
-                if count_depth(absolute) - 1 > max_depth {
-                    return false;
-                }
-                if entry.file_type().is_dir() {
-                    return true;
-                }
-                return is_image(entry.path());
+            for (path, e) in listing.errors {
+                on_event(ScanEvent::Unreadable {
+                    path: path.to_string_lossy().to_string(),
+                    reason: e.to_string(),
+                });
             }
-            false
-        };
 
-        for entry in walker.filter_entry(should_process) {
-            if entry.is_err() {
-                continue;
+            for (path, reason) in &listing.state.problems {
+                on_event(ScanEvent::BrokenSettings {
+                    path: path.to_string_lossy().to_string(),
+                    reason: reason.clone(),
+                });
             }
-            let entry = entry?;
 
-            if entry.file_type().is_dir() {
-                continue;
+            let dir_path = listing.path.to_string_lossy().to_string();
+            on_event(ScanEvent::Visit(dir_path.clone()));
+            dirs += 1;
+
+            if !listing.files.is_empty() {
+                images += listing.files.len();
+
+                let mut entry = DirEntry::new(dir_path, FolderSettings::clone(&listing.state));
+                entry.image_entries = listing
+                    .files
+                    .iter()
+                    .map(|path| ImageEntry {
+                        image_path: path.to_string_lossy().to_string(),
+                        image: None,
+                    })
+                    .collect();
+                entry.settings.sort_images(&mut entry.image_entries);
+                on_event(ScanEvent::Dir(entry));
             }
 
-            let parent = entry
-                .path()
-                .parent()
-                .ok_or_else(|| anyhow!("not found parent directory"))?
-                .to_string_lossy()
-                .to_string();
-
-            let dir_entries_index =
-                if let Some(index) = entries.iter().position(|e| e.dir_path == parent) {
-                    index
-                } else {
-                    entries.push(DirEntry::new(parent));
-                    entries.len() - 1
-                };
-
-            entries[dir_entries_index].image_entries.push(ImageEntry {
-                image_path: entry.path().to_string_lossy().to_string(),
-                image: None,
-            });
+            if progress.ready() {
+                on_event(ScanEvent::Progress { dirs, images });
+            }
         }
+        on_event(ScanEvent::Progress { dirs, images });
 
-        entries.retain(|e| !e.image_entries.is_empty());
-
-        Ok(entries)
+        Ok(())
     }
 }
 
-fn count_depth<T: ToString>(path: T) -> u32 {
-    path.to_string()
-        .chars()
-        .filter(|&c| c == path::MAIN_SEPARATOR)
-        .count() as u32
-}
+// `.gridx2.toml` and ignore files cascade, so every directory starts from the settings of its parent
+struct ScanVisitor;
 
-fn to_absolute<T: AsRef<Path>>(path: T) -> anyhow::Result<String> {
-    Ok(path::absolute(path)?.to_string_lossy().to_string())
-}
+impl Visitor for ScanVisitor {
+    type State = FolderSettings;
+
+    fn keep_dir(&self, parent: &FolderSettings, dir: &Path) -> bool {
+        !parent.is_ignored(dir, true)
+    }
+
+    fn enter(&self, parent: &FolderSettings, dir: &Path) -> FolderSettings {
+        parent.child(dir)
+    }
 
-fn is_image<T: AsRef<Path>>(path: T) -> bool {
-    let supported_extensions: [&str; 20] = [
-        "avif", "bmp", "dds", "ff", "gif", "hdr", "ico", "jpg", "jpeg", "jfif", "exr", "png",
-        "pbm", "pgm", "ppm", "qoi", "tga", "tif", "tiff", "webp",
-    ];
-    let ext = path.as_ref().extension();
-    if let Some(ext) = ext {
-        supported_extensions.contains(&ext.to_string_lossy().as_ref())
-    } else {
-        false
+    fn keep_file(&self, settings: &FolderSettings, path: &Path) -> bool {
+        settings.is_image(path) && !settings.is_ignored(path, false)
     }
 }
@@ -0,0 +1,356 @@
+use crate::APP_CONFIG;
+use crate::app_config::THUMBNAIL_SIZES;
+use crate::command_template;
+use crate::image_entry::ImageEntry;
+use crate::image_format::ImageFilter;
+use crate::natural_sort::NaturalKey;
+use anyhow::anyhow;
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+// Optional file in any browsed directory, applying to it and everything below it
+pub const FOLDER_CONFIG_NAME: &str = ".gridx2.toml";
+
+// Gitignore-style files read in every browsed directory. The last one is where "Hide Folder" writes its rules
+pub const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", ".gridx2ignore"];
+pub const HIDE_FILE_NAME: &str = IGNORE_FILE_NAMES[2];
+
+// Whether a change to `path` can change the settings of its folder and everything below
+pub fn is_settings_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| {
+        name == FOLDER_CONFIG_NAME || IGNORE_FILE_NAMES.iter().any(|ignore| name == *ignore)
+    })
+}
+
+// Adds a rule for `dir` to the `.gridx2ignore` next to it, so scans skip it from now on
+pub fn hide(dir: &Path) -> anyhow::Result<()> {
+    let (Some(parent), Some(name)) = (dir.parent(), dir.file_name()) else {
+        return Err(anyhow!("{} has no parent folder", dir.display()));
+    };
+
+    let path = parent.join(HIDE_FILE_NAME);
+    let mut content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!("/{}/\n", escape_pattern(&name.to_string_lossy())));
+
+    fs::write(&path, content)?;
+    Ok(())
+}
+
+// Folder names are matched literally, not as globs
+fn escape_pattern(name: &str) -> String {
+    let mut pattern = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') || (i == 0 && matches!(c, '#' | '!')) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Name,
+    NameReverse,
+    Modified,
+    ModifiedReverse,
+}
+
+impl SortOrder {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::NameReverse => "name_reverse",
+            Self::Modified => "modified",
+            Self::ModifiedReverse => "modified_reverse",
+        }
+    }
+}
+
+// Everything in a `.gridx2.toml` is optional, unset values are inherited from the parent folder
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FolderOverrides {
+    thumbnail_size: Option<u32>,
+    sort_order: Option<SortOrder>,
+    // Gitignore-style patterns, relative to the folder of the file
+    ignore_patterns: Vec<String>,
+    open_command: Option<Vec<String>>,
+    expanded: Option<bool>,
+}
+
+// What a click on a thumbnail runs when no application is saved for its extension
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenCommand {
+    pub command: Vec<String>,
+    // The `.gridx2.toml` that set it, None for the global setting
+    pub source: Option<PathBuf>,
+}
+
+// The settings in effect for one directory after every `.gridx2.toml` above it was applied
+#[derive(Debug, Clone)]
+pub struct FolderSettings {
+    pub thumbnail_size: u32,
+    pub sort_order: SortOrder,
+    pub open_command: OpenCommand,
+    pub expanded: bool,
+    pub show_hidden: bool,
+    pub ignore_patterns: Vec<String>,
+    // Outermost first, so later ones take precedence
+    ignores: Vec<Arc<Gitignore>>,
+    image_filter: Arc<ImageFilter>,
+    // The files that contributed, outermost first
+    pub sources: Vec<PathBuf>,
+    // Files in this folder itself that could not be used, with the reason
+    pub problems: Vec<(PathBuf, String)>,
+}
+
+impl FolderSettings {
+    // What applies before any `.gridx2.toml` is read
+    pub fn global() -> anyhow::Result<Self> {
+        let app_config = APP_CONFIG
+            .read()
+            .map_err(|_| anyhow!("Failed to lock app config"))?;
+
+        Ok(Self {
+            thumbnail_size: app_config.thumbnail_size,
+            sort_order: SortOrder::default(),
+            open_command: OpenCommand {
+                command: app_config.open_command.clone(),
+                source: None,
+            },
+            expanded: false,
+            show_hidden: app_config.show_hidden,
+            ignore_patterns: Vec::new(),
+            ignores: Vec::new(),
+            image_filter: Arc::new(ImageFilter::new(
+                app_config.image_extensions.as_deref(),
+                app_config.sniff_content,
+            )),
+            sources: Vec::new(),
+            problems: Vec::new(),
+        })
+    }
+
+    // Settings of the opened folder. The global ignore patterns are relative to it
+    pub fn root(root: &Path) -> anyhow::Result<Self> {
+        let mut settings = Self::global()?;
+
+        let ignore_patterns = APP_CONFIG
+            .read()
+            .map_err(|_| anyhow!("Failed to lock app config"))?
+            .ignore_patterns
+            .clone();
+        if !ignore_patterns.is_empty() {
+            let mut builder = GitignoreBuilder::new(root);
+            for pattern in &ignore_patterns {
+                builder.add_line(None, pattern)?;
+            }
+            settings.ignores.push(Arc::new(builder.build()?));
+            settings.ignore_patterns.extend(ignore_patterns);
+        }
+
+        Ok(settings.child(root))
+    }
+
+    // Settings of `dir`, which is either the opened folder itself or a direct child of the folder these belong to
+    pub fn child(&self, dir: &Path) -> Self {
+        let mut settings = self.clone();
+        settings.problems.clear();
+
+        // A broken file only loses its own rules, the rest of the tree is still shown
+        for name in IGNORE_FILE_NAMES {
+            let path = dir.join(name);
+            if path.is_file()
+                && let Err(e) = settings.add_ignore_file(dir, &path)
+            {
+                settings.problems.push((path, format!("{e:#}")));
+            }
+        }
+
+        let path = dir.join(FOLDER_CONFIG_NAME);
+        if path.is_file()
+            && let Err(e) = settings.apply(dir, &path)
+        {
+            settings.problems.push((path, format!("{e:#}")));
+        }
+        settings
+    }
+
+    // Walks from the opened folder down to `dir`. Returns None if `dir` is ignored on the way
+    pub fn resolve(root: &str, dir: &str) -> anyhow::Result<Option<Self>> {
+        let root = Path::new(root);
+        let relative = Path::new(dir).strip_prefix(root)?;
+
+        let mut path = root.to_path_buf();
+        let mut settings = Self::root(root)?;
+        for component in relative.components() {
+            path.push(component);
+            if settings.is_ignored(&path, true) {
+                return Ok(None);
+            }
+            settings = settings.child(&path);
+        }
+
+        Ok(Some(settings))
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if !self.show_hidden
+            && path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        {
+            return true;
+        }
+
+        // Like git, the deepest rule that matches decides, so a `!pattern` further down can show again
+        // what a parent folder or the global patterns hid
+        for ignore in self.ignores.iter().rev() {
+            match ignore.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+
+    pub fn is_image(&self, path: &Path) -> bool {
+        self.image_filter.is_image(path)
+    }
+
+    pub fn sort_images(&self, image_entries: &mut [ImageEntry]) {
+        match self.sort_order {
+            SortOrder::Name => {
+                image_entries.sort_by_cached_key(|e| NaturalKey::new(&e.image_path));
+            }
+            SortOrder::NameReverse => {
+                image_entries.sort_by_cached_key(|e| Reverse(NaturalKey::new(&e.image_path)));
+            }
+            SortOrder::Modified => {
+                image_entries.sort_by_cached_key(|e| {
+                    (modified(&e.image_path), NaturalKey::new(&e.image_path))
+                });
+            }
+            SortOrder::ModifiedReverse => {
+                image_entries.sort_by_cached_key(|e| {
+                    Reverse((modified(&e.image_path), NaturalKey::new(&e.image_path)))
+                });
+            }
+        }
+    }
+
+    // Shown as the tooltip of a section, so it is clear where its settings come from
+    pub fn describe(&self) -> String {
+        let mut lines = vec![
+            format!("Thumbnail size: {} px", self.thumbnail_size),
+            format!("Sort order: {}", self.sort_order.name()),
+            format!(
+                "Open command: {}",
+                command_template::to_text(&self.open_command.command)
+            ),
+            format!(
+                "Expanded by default: {}",
+                if self.expanded { "yes" } else { "no" }
+            ),
+        ];
+
+        if !self.ignore_patterns.is_empty() {
+            lines.push(format!("Ignored: {}", self.ignore_patterns.join(", ")));
+        }
+
+        if self.sources.is_empty() {
+            lines.push("From: global settings".into());
+        } else {
+            let sources: Vec<String> = self
+                .sources
+                .iter()
+                .map(|source| source.display().to_string())
+                .collect();
+            lines.push(format!("From: {}", sources.join(", ")));
+        }
+
+        lines.join("\n")
+    }
+
+    fn add_ignore_file(&mut self, dir: &Path, path: &Path) -> anyhow::Result<()> {
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(e) = builder.add(path) {
+            return Err(e.into());
+        }
+        self.ignores.push(Arc::new(builder.build()?));
+        self.sources.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn apply(&mut self, dir: &Path, path: &Path) -> anyhow::Result<()> {
+        let content = fs::read_to_string(path)?;
+        let overrides: FolderOverrides = toml::from_str(&content)?;
+
+        // Checked before anything is applied, so a broken file loses all of its rules
+        if let Some(thumbnail_size) = overrides.thumbnail_size
+            && !THUMBNAIL_SIZES.contains(&thumbnail_size)
+        {
+            return Err(anyhow!(
+                "thumbnail_size must be between {} and {}, not {thumbnail_size}",
+                THUMBNAIL_SIZES.start(),
+                THUMBNAIL_SIZES.end()
+            ));
+        }
+        if overrides
+            .open_command
+            .as_ref()
+            .is_some_and(|command| command.is_empty())
+        {
+            return Err(anyhow!("open_command must not be empty"));
+        }
+
+        if !overrides.ignore_patterns.is_empty() {
+            let mut builder = GitignoreBuilder::new(dir);
+            for pattern in &overrides.ignore_patterns {
+                builder.add_line(Some(path.to_path_buf()), pattern)?;
+            }
+            self.ignores.push(Arc::new(builder.build()?));
+            self.ignore_patterns.extend(overrides.ignore_patterns);
+        }
+        if let Some(thumbnail_size) = overrides.thumbnail_size {
+            self.thumbnail_size = thumbnail_size;
+        }
+        if let Some(sort_order) = overrides.sort_order {
+            self.sort_order = sort_order;
+        }
+        if let Some(command) = overrides.open_command {
+            self.open_command = OpenCommand {
+                command,
+                source: Some(path.to_path_buf()),
+            };
+        }
+        if let Some(expanded) = overrides.expanded {
+            self.expanded = expanded;
+        }
+        self.sources.push(path.to_path_buf());
+
+        Ok(())
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -1,18 +1,24 @@
-use crate::{APP_CONFIG, IMAGE_CACHE};
+use crate::IMAGE_CACHE;
+use crate::texture_cache::CacheKey;
+use crate::thumbnail_cache::{self, SourceInfo, ThumbnailFlavor};
 use anyhow::anyhow;
 use gtk4::gdk::Texture;
 use gtk4::prelude::Cast;
 use gtk4::{gdk, glib};
 use image::imageops::FilterType;
-use image::{GenericImageView, ImageReader};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use image::{DynamicImage, GenericImageView, ImageReader};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 static CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
 static CACHE_MISSES: AtomicUsize = AtomicUsize::new(0);
+static DISK_CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
 static DISK_LOAD_TIME_MS: AtomicUsize = AtomicUsize::new(0);
 static CACHE_ACCESS_TIME_NS: AtomicUsize = AtomicUsize::new(0);
+// The first failed write to the thumbnail cache, later ones most likely have the same cause
+static SAVE_FAILED: AtomicBool = AtomicBool::new(false);
+static SAVE_FAILURE: Mutex<Option<String>> = Mutex::new(None);
 
 #[derive(Debug, Clone)]
 pub struct ImageEntry {
@@ -21,25 +27,20 @@ pub struct ImageEntry {
 }
 
 impl ImageEntry {
-    pub fn load_image(&mut self) -> anyhow::Result<()> {
+    // Returns the file as it was when the texture was made
+    pub fn load_image(&mut self, thumbnail_size: u32) -> anyhow::Result<CacheKey> {
+        let cache_key = CacheKey::new(&self.image_path, thumbnail_size)?;
         if self.image.is_some() {
-            return Ok(());
+            return Ok(cache_key);
         }
 
-        let thumbnail_size = {
-            let app_config = APP_CONFIG
-                .read()
-                .map_err(|_| anyhow!("Failed to lock app config"))?;
-            app_config.thumbnail_size
-        };
-
         let cache_start = Instant::now();
         let cache_hit = {
             let mut image_cache = match IMAGE_CACHE.lock() {
                 Ok(cache) => cache,
                 Err(_) => return Err(anyhow!("Failed to lock image cache")),
             };
-            image_cache.get(&self.image_path).cloned()
+            image_cache.get(&cache_key).cloned()
         };
 
         let cache_time = cache_start.elapsed().as_nanos() as usize;
@@ -49,31 +50,29 @@ impl ImageEntry {
             CACHE_HITS.fetch_add(1, Ordering::Relaxed);
             //println!("Cache hit: {}ns - {}", cache_time, self.image_path);
             self.image = Some(texture);
-            return Ok(());
+            return Ok(cache_key);
         }
 
         CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
         let disk_start = Instant::now();
 
-        if let Ok(texture) = self.load_and_resize_image(thumbnail_size) {
-            let disk_time = disk_start.elapsed().as_millis() as usize;
-            DISK_LOAD_TIME_MS.fetch_add(disk_time, Ordering::Relaxed);
-            //println!("Disk load: {}ms - {}", disk_time, self.image_path);
+        let texture = self.load_and_resize_image(thumbnail_size)?;
+        let disk_time = disk_start.elapsed().as_millis() as usize;
+        DISK_LOAD_TIME_MS.fetch_add(disk_time, Ordering::Relaxed);
+        //println!("Disk load: {}ms - {}", disk_time, self.image_path);
 
-            let texture = Arc::new(texture);
-            self.image = Some(texture.clone());
+        let texture = Arc::new(texture);
+        self.image = Some(texture.clone());
 
-            if let Ok(mut image_cache) = IMAGE_CACHE.lock() {
-                image_cache.put(self.image_path.clone(), texture);
-            }
+        if let Ok(mut image_cache) = IMAGE_CACHE.lock() {
+            image_cache.put(cache_key.clone(), texture);
         }
 
-        Ok(())
+        Ok(cache_key)
     }
 
     fn load_and_resize_image(&self, thumbnail_size: u32) -> anyhow::Result<Texture> {
-        let path = &self.image_path;
-        let img = ImageReader::open(path)?.decode()?;
+        let img = self.load_source_image(thumbnail_size)?;
         let (width, height) = img.dimensions();
         let (rw, rh) = self.calculate_size(width, height, thumbnail_size);
         let resized = img.resize(rw, rh, FilterType::Triangle);
@@ -92,6 +91,39 @@ impl ImageEntry {
         Ok(texture)
     }
 
+    // Prefers a thumbnail from the shared on-disk cache and writes one back after a full decode
+    fn load_source_image(&self, thumbnail_size: u32) -> anyhow::Result<DynamicImage> {
+        let path = &self.image_path;
+        let flavor = ThumbnailFlavor::for_size(thumbnail_size);
+        let source = SourceInfo::new(path);
+
+        if let (Some(flavor), Ok(source)) = (flavor, &source)
+            && let Some(img) = thumbnail_cache::load(source, flavor)
+        {
+            DISK_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+            return Ok(img);
+        }
+
+        // The content decides the decoder, so misnamed and extensionless files still work
+        let img = ImageReader::open(path)?.with_guessed_format()?.decode()?;
+
+        if let (Some(flavor), Ok(source)) = (flavor, &source) {
+            let thumbnail = thumbnail_cache::make_thumbnail(&img, flavor);
+            if let Err(e) = thumbnail_cache::save(source, flavor, &thumbnail) {
+                if !SAVE_FAILED.swap(true, Ordering::Relaxed)
+                    && let Ok(mut failure) = SAVE_FAILURE.lock()
+                {
+                    *failure = Some(format!(
+                        "Thumbnails can not be saved to the shared cache ({path}: {e})"
+                    ));
+                }
+            }
+            return Ok(thumbnail);
+        }
+
+        Ok(img)
+    }
+
     fn calculate_size(&self, mut width: u32, mut height: u32, to: u32) -> (u32, u32) {
         match width > height {
             true => {
@@ -108,9 +140,52 @@ impl ImageEntry {
     }
 }
 
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub used_bytes: usize,
+    pub budget_bytes: usize,
+}
+
+// Runs on the main thread, so it trusts `loaded_from` instead of checking the file again. Rewritten files
+// are reloaded, which drops what they were loaded from
+pub fn cached_texture(loaded_from: &CacheKey, thumbnail_size: u32) -> Option<Arc<Texture>> {
+    let cache_key = CacheKey {
+        thumbnail_size,
+        ..loaded_from.clone()
+    };
+    IMAGE_CACHE.lock().ok()?.get(&cache_key).cloned()
+}
+
+pub fn is_cached(image_path: &str, thumbnail_size: u32) -> bool {
+    IMAGE_CACHE
+        .lock()
+        .is_ok_and(|image_cache| image_cache.contains(image_path, thumbnail_size))
+}
+
+pub fn cache_stats() -> CacheStats {
+    let (used_bytes, budget_bytes) = match IMAGE_CACHE.lock() {
+        Ok(image_cache) => (image_cache.used_bytes(), image_cache.budget_bytes()),
+        Err(_) => (0, 0),
+    };
+
+    CacheStats {
+        hits: CACHE_HITS.load(Ordering::Relaxed),
+        misses: CACHE_MISSES.load(Ordering::Relaxed),
+        used_bytes,
+        budget_bytes,
+    }
+}
+
+// Taken once, by the main thread that can show it
+pub fn take_save_failure() -> Option<String> {
+    SAVE_FAILURE.lock().ok()?.take()
+}
+
 pub fn clear_cache() {
     CACHE_HITS.store(0, Ordering::Relaxed);
     CACHE_MISSES.store(0, Ordering::Relaxed);
+    DISK_CACHE_HITS.store(0, Ordering::Relaxed);
     DISK_LOAD_TIME_MS.store(0, Ordering::Relaxed);
     CACHE_ACCESS_TIME_NS.store(0, Ordering::Relaxed);
 }
@@ -118,6 +193,7 @@ pub fn clear_cache() {
 pub fn show_cache_stats() {
     let hits = CACHE_HITS.load(Ordering::Relaxed);
     let misses = CACHE_MISSES.load(Ordering::Relaxed);
+    let disk_hits = DISK_CACHE_HITS.load(Ordering::Relaxed);
     let total = hits + misses;
 
     if total > 0 {
@@ -136,7 +212,7 @@ pub fn show_cache_stats() {
         println!("\nCache stats:");
         println!("Total accesses: {total}");
         println!("Cache hits: {hits} ({hits_percent:.2}%)");
-        println!("Cache misses: {misses}");
+        println!("Cache misses: {misses} ({disk_hits} served from thumbnail cache)");
         println!("Average disk read time: {avg_disk_time:.2}ms");
         println!(
             "Average cache access time: {avg_cache_time_ms:.2}ms (total {avg_cache_time_ns:.2}ns)"
@@ -0,0 +1,133 @@
+use image::ImageFormat;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Enough for every signature `image::guess_format` knows
+const HEADER_LEN: u64 = 64;
+
+// Extensions of every format the compiled-in decoders can read
+pub fn supported_extensions() -> Vec<&'static str> {
+    ImageFormat::all()
+        .filter(ImageFormat::reading_enabled)
+        .flat_map(ImageFormat::extensions_str)
+        .copied()
+        .collect()
+}
+
+pub fn is_readable(ext: &str) -> bool {
+    ImageFormat::from_extension(ext).is_some_and(|format| format.reading_enabled())
+}
+
+// Decides which files the scanner treats as images
+#[derive(Debug, Clone)]
+pub struct ImageFilter {
+    // None accepts every extension a decoder is compiled in for
+    extensions: Option<HashSet<String>>,
+    sniff_content: bool,
+}
+
+impl ImageFilter {
+    pub fn new(extensions: Option<&[String]>, sniff_content: bool) -> Self {
+        let extensions = extensions.map(|extensions| {
+            extensions
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                // Listing a format without a decoder would only produce broken tiles
+                .filter(|ext| is_readable(ext))
+                .collect()
+        });
+
+        Self {
+            extensions,
+            sniff_content,
+        }
+    }
+
+    pub fn is_image(&self, path: &Path) -> bool {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        if let Some(ext) = &ext {
+            if self.accepts(ext) {
+                return true;
+            }
+            // A known image extension left out of the list stays left out
+            if is_readable(ext) {
+                return false;
+            }
+        }
+
+        // Unknown or missing extensions are only looked into when asked to, since it means opening every file
+        self.sniff_content
+            && sniff(path).is_some_and(|format| {
+                format.reading_enabled()
+                    && format.extensions_str().iter().any(|ext| self.accepts(ext))
+            })
+    }
+
+    fn accepts(&self, ext: &str) -> bool {
+        match &self.extensions {
+            Some(extensions) => extensions.contains(ext),
+            None => is_readable(ext),
+        }
+    }
+}
+
+fn sniff(path: &Path) -> Option<ImageFormat> {
+    let mut header = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(HEADER_LEN)
+        .read_to_end(&mut header)
+        .ok()?;
+    image::guess_format(&header).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // The signature `image::guess_format` looks for
+    const GIF_HEADER: &[u8] = b"GIF89a";
+
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("gridx2-format-{}-{name}", std::process::id()));
+            fs::write(&path, content).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn sniffs_unknown_and_missing_extensions() {
+        let filter = ImageFilter::new(None, true);
+
+        assert!(filter.is_image(&TempFile::new("gif.dat", GIF_HEADER).0));
+        assert!(filter.is_image(&TempFile::new("gif", GIF_HEADER).0));
+        assert!(!filter.is_image(&TempFile::new("text.dat", b"text").0));
+        assert!(!ImageFilter::new(None, false).is_image(&TempFile::new("off.dat", GIF_HEADER).0));
+    }
+
+    #[test]
+    fn keeps_excluded_formats_out_when_sniffing() {
+        let filter = ImageFilter::new(Some(&["png".to_string()]), true);
+
+        assert!(filter.is_image(Path::new("a.PNG")));
+        // Not opened at all, its extension alone rules it out
+        assert!(!filter.is_image(&TempFile::new("excluded.gif", GIF_HEADER).0));
+        assert!(!filter.is_image(&TempFile::new("excluded.dat", GIF_HEADER).0));
+    }
+}
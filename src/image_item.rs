@@ -0,0 +1,88 @@
+use crate::texture_cache::CacheKey;
+use gtk4::gdk;
+use gtk4::glib;
+use gtk4::glib::Object;
+use gtk4::subclass::prelude::ObjectSubclassIsExt;
+
+mod imp {
+    use crate::texture_cache::CacheKey;
+    use gtk4::gdk;
+    use gtk4::glib;
+    use gtk4::glib::Properties;
+    use gtk4::prelude::*;
+    use gtk4::subclass::prelude::*;
+    use std::cell::{Cell, RefCell};
+
+    #[derive(Properties, Default)]
+    #[properties(wrapper_type = super::ImageItem)]
+    pub struct ImageItem {
+        #[property(get, set)]
+        path: RefCell<String>,
+        // Sections can use different sizes, so each item knows the size its texture is made for
+        #[property(get, set)]
+        thumbnail_size: Cell<u32>,
+        #[property(get, set, nullable)]
+        texture: RefCell<Option<gdk::Texture>>,
+        // Set while a decode for this item is queued, so rebinding does not request it twice
+        #[property(get, set)]
+        loading: Cell<bool>,
+        #[property(get, set)]
+        failed: Cell<bool>,
+        // Why the last decode failed, empty otherwise
+        #[property(get, set)]
+        error: RefCell<String>,
+        // The file as it was when the texture was decoded, so the memory cache can be asked without touching the disk
+        pub loaded_from: RefCell<Option<CacheKey>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ImageItem {
+        const NAME: &'static str = "Gridx2ImageItem";
+        type Type = super::ImageItem;
+    }
+
+    #[glib::derived_properties]
+    impl ObjectImpl for ImageItem {}
+}
+
+glib::wrapper! {
+    pub struct ImageItem(ObjectSubclass<imp::ImageItem>);
+}
+
+impl ImageItem {
+    pub fn new(path: &str, thumbnail_size: u32) -> Self {
+        Object::builder()
+            .property("path", path)
+            .property("thumbnail-size", thumbnail_size)
+            .build()
+    }
+
+    pub fn needs_texture(&self) -> bool {
+        self.texture().is_none() && !self.loading() && !self.failed()
+    }
+
+    pub fn release_texture(&self) {
+        if !self.loading() {
+            self.set_texture(None::<&gdk::Texture>);
+        }
+    }
+
+    pub fn loaded_from(&self) -> Option<CacheKey> {
+        self.imp().loaded_from.borrow().clone()
+    }
+
+    pub fn set_loaded_from(&self, key: Option<CacheKey>) {
+        self.imp().loaded_from.replace(key);
+    }
+
+    pub fn set_loaded(&self, texture: Option<&gdk::Texture>) {
+        self.set_loading(false);
+        self.set_failed(texture.is_none());
+        self.set_texture(texture);
+    }
+
+    pub fn set_broken(&self, error: &str) {
+        self.set_error(error);
+        self.set_loaded(None);
+    }
+}
@@ -1,69 +1,221 @@
-use crate::open_with_xdg_open;
+use crate::accordion_widget::{self, LoadProgress};
+use crate::folder_config::OpenCommand;
+use crate::image_item::ImageItem;
+use crate::open_with;
+use crate::problems::Problems;
+use crate::thumbnail_loader::ThumbnailLoader;
+use crate::thumbnail_menu::ThumbnailMenu;
 use gtk4 as gtk;
-use gtk4::gdk::Texture;
-use gtk4::prelude::{BoxExt, TextureExt, WidgetExt};
-use gtk4::Picture;
-use std::cell::RefCell;
-use std::rc::Rc;
+use gtk4::prelude::{
+    BoxExt, Cast, CastNone, EventControllerExt, GObjectPropertyExpressionExt, GestureSingleExt,
+    ListItemExt, StaticType, WidgetExt,
+};
+use gtk4::{GridView, ListItem, Picture, SignalListItemFactory, Widget, gdk, glib, pango};
+use std::path::Path;
 
 #[derive(Clone)]
 pub struct ImageWidget {
     widget: gtk::Box,
     picture: Picture,
-    image_path: Rc<RefCell<Option<String>>>,
+    // Shown instead of the picture when the file could not be decoded
+    placeholder: gtk::Box,
+    placeholder_label: gtk::Label,
 }
 
 impl ImageWidget {
-    pub fn new() -> Self {
+    pub fn new(thumbnail_size: i32) -> Self {
         let widget = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        widget.set_size_request(thumbnail_size, thumbnail_size);
         widget.set_halign(gtk::Align::Center);
         widget.set_valign(gtk::Align::Center);
 
         let picture = Picture::new();
         picture.set_halign(gtk::Align::Center);
         picture.set_valign(gtk::Align::Center);
+        picture.set_vexpand(true);
+        picture.set_content_fit(gtk::ContentFit::ScaleDown);
 
-        widget.append(&picture);
+        let placeholder = gtk::Box::new(gtk::Orientation::Vertical, 4);
+        placeholder.add_css_class("broken-image");
+        placeholder.set_valign(gtk::Align::Center);
+        placeholder.set_vexpand(true);
+        placeholder.set_visible(false);
+
+        let icon = gtk::Image::from_icon_name("image-missing");
+        icon.set_pixel_size(thumbnail_size / 3);
+
+        let placeholder_label = gtk::Label::new(None);
+        placeholder_label.set_ellipsize(pango::EllipsizeMode::Middle);
+        placeholder_label.set_max_width_chars(20);
 
-        let image_path = Rc::new(RefCell::new(None));
+        placeholder.append(&icon);
+        placeholder.append(&placeholder_label);
+
+        widget.append(&picture);
+        widget.append(&placeholder);
 
-        let image_widget = Self {
+        Self {
             widget,
             picture,
-            image_path,
-        };
+            placeholder,
+            placeholder_label,
+        }
+    }
 
-        image_widget.setup_click_handler();
+    // Tiles are recycled by the grid, so everything is looked up through the list item they currently show
+    pub fn factory(
+        thumbnail_size: i32,
+        open_command: OpenCommand,
+        loader: &ThumbnailLoader,
+        progress: &LoadProgress,
+    ) -> SignalListItemFactory {
+        let factory = SignalListItemFactory::new();
 
-        image_widget
-    }
+        let problems = loader.problems().clone();
+        factory.connect_setup(move |_, list_item| {
+            let Some(list_item) = list_item.downcast_ref::<ListItem>() else {
+                return;
+            };
 
-    fn setup_click_handler(&self) {
-        let image_path = self.image_path.borrow().clone();
+            let image_widget = ImageWidget::new(thumbnail_size);
+            image_widget.setup_click_handler(list_item, open_command.clone(), &problems);
+            image_widget.setup_context_menu(list_item, &problems);
 
-        let click_gesture = gtk::GestureClick::new();
-        click_gesture.connect_released(move |_gesture, _n_press, _x, _y| {
-            let image_path = image_path.clone();
-            if let Some(path) = image_path {
-                let err = open_with_xdg_open(path);
-                if err.is_err() {
-                    println!("Failed to open image: {}", err.unwrap_err());
+            list_item
+                .property_expression("item")
+                .chain_property::<ImageItem>("texture")
+                .bind(&image_widget.picture, "paintable", Widget::NONE);
+            image_widget.bind_placeholder(list_item);
+
+            list_item.set_child(Some(image_widget.widget()));
+        });
+
+        let loader = loader.clone();
+        let progress = progress.clone();
+        factory.connect_bind(glib::clone!(
+            #[strong]
+            loader,
+            move |_, list_item| {
+                let Some(list_item) = list_item.downcast_ref::<ListItem>() else {
+                    return;
+                };
+                if let (Some(item), Some(tile)) = (
+                    list_item.item().and_downcast::<ImageItem>(),
+                    list_item.child(),
+                ) {
+                    loader.bind(&item, &tile, &progress);
                 }
             }
+        ));
+
+        factory.connect_unbind(move |_, list_item| {
+            if let Some(item) = list_item
+                .downcast_ref::<ListItem>()
+                .and_then(|list_item| list_item.item())
+                .and_downcast::<ImageItem>()
+            {
+                loader.unbind(&item);
+            }
         });
 
-        self.picture.add_controller(click_gesture);
+        factory
+    }
+
+    fn setup_click_handler(
+        &self,
+        list_item: &ListItem,
+        open_command: OpenCommand,
+        problems: &Problems,
+    ) {
+        let problems = problems.clone();
+        let click_gesture = gtk::GestureClick::new();
+        click_gesture.connect_released(glib::clone!(
+            #[weak]
+            list_item,
+            move |gesture, _n_press, _x, _y| {
+                // Ctrl and Shift clicks only change the selection
+                let state = gesture.current_event_state();
+                if state.intersects(gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::SHIFT_MASK)
+                {
+                    return;
+                }
+
+                if let Some(item) = list_item.item().and_downcast::<ImageItem>() {
+                    open_with::open_or_report(&item.path(), &open_command, &problems);
+                }
+            }
+        ));
+
+        // On the whole tile, so broken images can be opened as well
+        self.widget.add_controller(click_gesture);
     }
 
-    pub fn set_image(&mut self, path: &str, texture: &Texture) {
-        self.picture.set_paintable(Some(texture));
-        self.picture
-            .set_size_request(texture.width(), texture.height());
-        self.image_path.replace(Some(path.to_string()));
-        self.setup_click_handler();
+    fn bind_placeholder(&self, list_item: &ListItem) {
+        let item = list_item.property_expression("item");
+        let failed = item.chain_property::<ImageItem>("failed");
+        let path = item.chain_property::<ImageItem>("path");
+        let error = item.chain_property::<ImageItem>("error");
+
+        failed.bind(&self.placeholder, "visible", Widget::NONE);
+        failed
+            .chain_closure_with_callback(|values| !values[1].get::<bool>().unwrap_or(false))
+            .bind(&self.picture, "visible", Widget::NONE);
+
+        path.chain_closure_with_callback(|values| {
+            let path = values[1].get::<String>().unwrap_or_default();
+            file_name(&path)
+        })
+        .bind(&self.placeholder_label, "label", Widget::NONE);
+
+        gtk::ClosureExpression::with_callback([&path, &error], |values| {
+            let path = values[1].get::<String>().unwrap_or_default();
+            let error = values[2].get::<String>().unwrap_or_default();
+            format!("{}\n{error}", file_name(&path))
+        })
+        .bind(&self.placeholder, "tooltip-text", Widget::NONE);
+    }
+
+    fn setup_context_menu(&self, list_item: &ListItem, problems: &Problems) {
+        let menu = ThumbnailMenu::new(&self.widget, problems);
+
+        let click_gesture = gtk::GestureClick::new();
+        click_gesture.set_button(gdk::BUTTON_SECONDARY);
+        click_gesture.connect_pressed(glib::clone!(
+            #[weak]
+            list_item,
+            move |gesture, _n_press, x, y| {
+                let Some(item) = list_item.item().and_downcast::<ImageItem>() else {
+                    return;
+                };
+                let path = item.path();
+
+                let selection = gesture
+                    .widget()
+                    .and_then(|widget| widget.ancestor(GridView::static_type()))
+                    .and_downcast::<GridView>()
+                    .and_then(|grid_view| grid_view.model());
+                let targets = match selection {
+                    Some(selection) if list_item.is_selected() => {
+                        accordion_widget::selected_paths(&selection)
+                    }
+                    _ => vec![path.clone()],
+                };
+
+                menu.popup(&path, &targets, x, y);
+            }
+        ));
+
+        self.widget.add_controller(click_gesture);
     }
 
     pub fn widget(&self) -> &gtk::Box {
         &self.widget
     }
 }
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
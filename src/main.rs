@@ -1,75 +1,437 @@
 mod accordion_widget;
+mod action_output;
 mod app_config;
+mod command_template;
+mod config_watcher;
+mod custom_action;
+mod dir_watcher;
 mod entry;
+mod folder_config;
 mod image_entry;
+mod image_format;
+mod image_item;
 mod image_widget;
+mod natural_sort;
+mod open_with;
+mod parallel_walk;
+mod problems;
+mod scan_queue;
 mod settings_window;
-
-use crate::accordion_widget::AccordionWidget;
-use crate::app_config::AppConfig;
-use crate::image_entry::{ImageEntry, clear_cache, show_cache_stats};
+mod texture_cache;
+mod thumbnail_cache;
+mod thumbnail_loader;
+mod thumbnail_menu;
+mod thumbnail_scheduler;
+
+use crate::accordion_widget::{AccordionWidget, SectionSnapshot};
+use crate::action_output::ActionOutput;
+use crate::app_config::{AppConfig, ConfigOverrides};
+use crate::config_watcher::ConfigWatcher;
+use crate::custom_action::{RUN_ACTION, RUN_ACTION_ON};
+use crate::dir_watcher::{DirWatcher, FsChanges};
+use crate::entry::ScanEvent;
+use crate::folder_config::{self, FolderSettings};
+use crate::image_entry::{cache_stats, clear_cache};
+use crate::image_item::ImageItem;
 use crate::image_widget::ImageWidget;
+use crate::problems::{ProblemKind, Problems};
+use crate::scan_queue::Drained;
 use crate::settings_window::SettingsWindow;
+use crate::texture_cache::{CacheKey, TextureCache, format_size};
+use crate::thumbnail_loader::ThumbnailLoader;
 use anyhow::anyhow;
+use futures_channel::oneshot;
 use gtk4 as gtk;
-use gtk4::gdk::Texture;
 use gtk4::gio::Cancellable;
 use gtk4::prelude::{
-    ActionMapExt, ApplicationExt, ApplicationExtManual, ApplicationWindowExt, BoxExt, FileExt,
-    GtkApplicationExt, GtkWindowExt, WidgetExt,
+    ActionMapExt, AdjustmentExt, ApplicationCommandLineExt, ApplicationExt, ApplicationExtManual,
+    ApplicationWindowExt, BoxExt, ButtonExt, CastNone, FileExt, GtkApplicationExt, GtkWindowExt,
+    ListModelExt, ListModelExtManual, StaticVariantType, WidgetExt,
 };
 use gtk4::{Application, ApplicationWindow, CssProvider, FileDialog, gdk, gio, glib};
-use lru::LruCache;
-use rayon::prelude::*;
-use regex::Regex;
 use std::cell::RefCell;
-use std::cmp::{Ordering, min};
-use std::num::NonZero;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::path::Path;
-use std::process::{Command, Stdio};
 use std::rc::Rc;
+use std::sync::atomic::{self, AtomicBool};
 use std::sync::{Arc, LazyLock, Mutex, RwLock, mpsc};
 use std::thread;
 use std::time::Duration;
 
-static APP_CONFIG: LazyLock<RwLock<AppConfig>> =
-    LazyLock::new(|| RwLock::new(AppConfig::load().unwrap_or_default()));
-static IMAGE_CACHE: LazyLock<Mutex<LruCache<String, Arc<Texture>>>> =
-    LazyLock::new(|| Mutex::new(LruCache::new(NonZero::new(5000).unwrap())));
+static APP_CONFIG: LazyLock<RwLock<AppConfig>> = LazyLock::new(|| RwLock::new(load_app_config()));
+// Why the config file could not be used, shown once the window is up
+static CONFIG_PROBLEM: Mutex<Option<String>> = Mutex::new(None);
+static CONFIG_OVERRIDES: Mutex<ConfigOverrides> = Mutex::new(ConfigOverrides::new());
+static IMAGE_CACHE: LazyLock<Mutex<TextureCache>> = LazyLock::new(|| {
+    let budget = match APP_CONFIG.read() {
+        Ok(app_config) => app_config.cache_budget_bytes(),
+        Err(_) => AppConfig::default().cache_budget_bytes(),
+    };
+    Mutex::new(TextureCache::new(budget))
+});
+
+// Time spent on scan events per main loop iteration, so the window stays responsive during large scans
+const SCAN_TICK_BUDGET: Duration = Duration::from_millis(8);
+// How long to wait for the scanner once it has nothing new
+const SCAN_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 struct AppState {
+    // Empty until a folder is opened
     original_dir: String,
     dir_entries: Vec<entry::DirEntry>,
+    scan_cancel: Option<Arc<AtomicBool>>,
+    // Image to scroll to once the scan reaches its directory
+    focus_path: Option<String>,
 }
 
 struct AppUI {
     top_vbox: gtk::Box,
+    status_label: gtk::Label,
+    cancel_button: gtk::Button,
+    thumbnail_loader: ThumbnailLoader,
+    accordions: HashMap<String, Rc<RefCell<AccordionWidget>>>,
+    dir_watcher: DirWatcher,
+    config_watcher: Option<ConfigWatcher>,
+    action_output: ActionOutput,
+    actions_menu: gio::Menu,
+    problems: Problems,
 }
 
 impl AppState {
     fn new() -> Self {
         Self {
-            original_dir: String::from("."),
+            original_dir: String::new(),
             dir_entries: Vec::new(),
+            scan_cancel: None,
+            focus_path: None,
         }
     }
 }
 
+#[derive(Clone)]
+struct MainWindow {
+    window: ApplicationWindow,
+    app_state: Arc<Mutex<AppState>>,
+    app_ui: Rc<RefCell<AppUI>>,
+}
+
 fn main() -> glib::ExitCode {
     gtk::init().expect("Failed to initialize GTK");
 
+    // A second `gridx2 [OPTIONS] DIR` hands its options and DIR to the running instance
     let app = Application::builder()
         .application_id("me.bluegecko.gridx2")
+        .flags(gio::ApplicationFlags::HANDLES_COMMAND_LINE)
         .build();
 
-    app.connect_activate(move |app| {
-        build_ui(app);
+    add_main_options(&app);
+    app.connect_handle_local_options(|_, options| check_main_options(options));
+
+    let main_window: Rc<RefCell<Option<MainWindow>>> = Rc::new(RefCell::new(None));
+
+    app.connect_activate(glib::clone!(
+        #[strong]
+        main_window,
+        move |app| {
+            get_main_window(app, &main_window).window.present();
+        }
+    ));
+
+    // Runs in the first instance, for its own command line and for every later one
+    app.connect_command_line(move |app, command_line| {
+        let is_running = main_window.borrow().is_some();
+        let changes = apply_main_options(&command_line.options_dict());
+        let main_window = get_main_window(app, &main_window);
+
+        // A new window is already built with the options in place
+        if is_running && let Some((previous, current)) = changes {
+            apply_config_changes(
+                &main_window.app_state,
+                &main_window.app_ui,
+                &previous,
+                &current,
+            );
+        }
+        // Relative paths are resolved against the directory the command was run in
+        if let Some(arg) = command_line.arguments().get(1)
+            && let Some(path) = command_line.create_file_for_arg(arg).path()
+        {
+            open_path(&main_window.app_state, &main_window.app_ui, &path);
+        }
+        main_window.window.present();
+        0
     });
 
     app.run()
 }
 
-fn build_ui(app: &Application) {
+fn add_main_options(app: &Application) {
+    app.add_main_option(
+        "depth",
+        glib::Char::from(b'd'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Int,
+        "Maximum directory depth to scan",
+        Some("DEPTH"),
+    );
+    app.add_main_option(
+        "thumbnail-size",
+        glib::Char::from(b's'),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::Int,
+        "Thumbnail size in pixels",
+        Some("SIZE"),
+    );
+}
+
+fn read_main_options(options: &glib::VariantDict) -> (Option<i32>, Option<i32>) {
+    let depth = options.lookup::<i32>("depth").ok().flatten();
+    let thumbnail_size = options.lookup::<i32>("thumbnail-size").ok().flatten();
+    (depth, thumbnail_size)
+}
+
+// Runs in the process that was started, so mistakes are shown where they were made. Returns -1 to let
+// the application carry on
+fn check_main_options(options: &glib::VariantDict) -> i32 {
+    let (depth, thumbnail_size) = read_main_options(options);
+
+    if depth.is_some_and(|depth| depth < 0) {
+        eprintln!("--depth must not be negative");
+        return 1;
+    }
+    if thumbnail_size.is_some_and(|size| size <= 0) {
+        eprintln!("--thumbnail-size must be positive");
+        return 1;
+    }
+
+    -1
+}
+
+// Overrides the configured values for the rest of the session, leaving the ones that were not given as
+// they are. Returns the config before and after if anything was given
+fn apply_main_options(options: &glib::VariantDict) -> Option<(AppConfig, AppConfig)> {
+    let (depth, thumbnail_size) = read_main_options(options);
+    if depth.is_none() && thumbnail_size.is_none() {
+        return None;
+    }
+
+    // Kept so reloading the config file does not undo them
+    let mut overrides = CONFIG_OVERRIDES.lock().ok()?;
+    if let Some(depth) = depth {
+        overrides.max_depth = Some(depth as u32);
+    }
+    if let Some(thumbnail_size) = thumbnail_size {
+        overrides.thumbnail_size = Some(thumbnail_size as u32);
+    }
+
+    let mut app_config = APP_CONFIG.write().ok()?;
+    let previous = app_config.clone();
+    overrides.apply(&mut app_config);
+    Some((previous, app_config.clone()))
+}
+
+fn load_app_config() -> AppConfig {
+    match AppConfig::load() {
+        Ok(app_config) => app_config,
+        Err(e) => {
+            eprintln!("Failed to load config: {e}");
+            if let Ok(mut problem) = CONFIG_PROBLEM.lock() {
+                *problem = Some(e.to_string());
+            }
+            AppConfig::default()
+        }
+    }
+}
+
+fn report_config_problem(window: &ApplicationWindow) {
+    let problem = match CONFIG_PROBLEM.lock() {
+        Ok(mut problem) => problem.take(),
+        Err(_) => None,
+    };
+
+    if let Some(problem) = problem {
+        gtk::AlertDialog::builder()
+            .modal(true)
+            .message("The settings could not be loaded, the defaults are used instead")
+            .detail(problem)
+            .build()
+            .show(Some(window));
+    }
+}
+
+// The config file was written, by the settings window or from outside
+fn reload_app_config(app_state: &Arc<Mutex<AppState>>, app_ui: &Rc<RefCell<AppUI>>) {
+    let problems = app_ui.borrow().problems.clone();
+    let config_path = match AppConfig::config_path() {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) => return,
+    };
+    let result = AppConfig::reload();
+    show_config_warnings(&problems);
+    let mut app_config = match result {
+        Ok(Some(app_config)) => app_config,
+        Ok(None) => {
+            problems.resolve(ProblemKind::Config, &config_path);
+            return;
+        }
+        // The settings in use stay as they are until the file can be read again
+        Err(e) => {
+            problems.report(
+                ProblemKind::Config,
+                &config_path,
+                &format!("{e:#}. The previous settings stay in effect"),
+                None::<fn()>,
+            );
+            return;
+        }
+    };
+    problems.resolve(ProblemKind::Config, &config_path);
+
+    if let Ok(overrides) = CONFIG_OVERRIDES.lock() {
+        overrides.apply(&mut app_config);
+    }
+
+    let previous = match APP_CONFIG.write() {
+        Ok(mut current) => std::mem::replace(&mut *current, app_config.clone()),
+        Err(_) => return,
+    };
+    apply_config_changes(app_state, app_ui, &previous, &app_config);
+    show_config_warnings(&problems);
+}
+
+fn show_config_warnings(problems: &Problems) {
+    let warnings = app_config::take_warnings();
+    if !warnings.is_empty() {
+        problems.notify(&warnings.join("\n"));
+    }
+}
+
+// Does only the work each changed field needs
+fn apply_config_changes(
+    app_state: &Arc<Mutex<AppState>>,
+    app_ui: &Rc<RefCell<AppUI>>,
+    previous: &AppConfig,
+    current: &AppConfig,
+) {
+    if previous.dark_mode != current.dark_mode {
+        let dark_mode = current.is_dark_mode();
+        for accordion_widget in app_ui.borrow().accordions.values() {
+            accordion_widget.borrow().set_dark_mode(dark_mode);
+        }
+    }
+
+    if previous.cache_budget != current.cache_budget
+        && let Ok(mut image_cache) = IMAGE_CACHE.lock()
+    {
+        image_cache.set_budget(current.cache_budget_bytes());
+    }
+
+    if previous.actions != current.actions
+        && let Some(app) = gio::Application::default().and_downcast::<Application>()
+    {
+        let app_ui = app_ui.borrow();
+        custom_action::install(
+            &app,
+            &app_ui.actions_menu,
+            &current.actions,
+            &app_ui.problems,
+        );
+    }
+
+    let is_open = app_state
+        .lock()
+        .is_ok_and(|app_state| !app_state.original_dir.is_empty());
+    if !is_open {
+        return;
+    }
+
+    // A new depth, image filter, ignore rule or walking option changes which folders exist at all, which a fresh scan takes care of
+    if previous.max_depth != current.max_depth
+        || previous.image_extensions != current.image_extensions
+        || previous.sniff_content != current.sniff_content
+        || previous.ignore_patterns != current.ignore_patterns
+        || previous.show_hidden != current.show_hidden
+        || previous.follow_symlinks != current.follow_symlinks
+        || previous.same_file_system != current.same_file_system
+    {
+        spawn_update_entry(app_state, app_ui);
+        return;
+    }
+
+    if previous.thumbnail_size != current.thumbnail_size
+        || previous.open_command != current.open_command
+    {
+        refresh_sections(app_state, app_ui);
+    }
+}
+
+// Global values cascade into every folder, so each section works out its settings again
+fn refresh_sections(app_state: &Arc<Mutex<AppState>>, app_ui: &Rc<RefCell<AppUI>>) {
+    let original_dir = match app_state.lock() {
+        Ok(app_state) => app_state.original_dir.clone(),
+        Err(_) => return,
+    };
+    let (accordions, thumbnail_loader) = {
+        let app_ui = app_ui.borrow();
+        let accordions: Vec<_> = app_ui
+            .accordions
+            .iter()
+            .map(|(dir_path, accordion_widget)| (dir_path.clone(), accordion_widget.clone()))
+            .collect();
+        (accordions, app_ui.thumbnail_loader.clone())
+    };
+
+    for (dir_path, accordion_widget) in accordions {
+        let settings = match FolderSettings::resolve(&original_dir, &dir_path) {
+            Ok(Some(settings)) => settings,
+            Ok(None) => continue,
+            Err(e) => {
+                thumbnail_loader
+                    .problems()
+                    .notify(&format!("Failed to refresh {dir_path}: {e}"));
+                continue;
+            }
+        };
+
+        accordion_widget.borrow().set_details(&settings.describe());
+        if let Ok(mut app_state) = app_state.lock()
+            && let Some(dir_entry) = app_state
+                .dir_entries
+                .iter_mut()
+                .find(|e| e.dir_path == dir_path)
+        {
+            dir_entry.settings = settings;
+        }
+
+        // Sections that were never expanded pick up the new settings once they are
+        if accordion_widget.borrow().snapshot.is_none() {
+            continue;
+        }
+        if let Err(e) =
+            refresh_accordion(app_state, &accordion_widget, &dir_path, &thumbnail_loader)
+        {
+            thumbnail_loader
+                .problems()
+                .notify(&format!("Failed to refresh {dir_path}: {e}"));
+        }
+
+        let accordion_widget = accordion_widget.borrow();
+        if accordion_widget.expander.is_expanded() {
+            thumbnail_loader.prefetch(&accordion_widget.store, &accordion_widget.progress);
+        }
+    }
+    thumbnail_loader.queue_reprioritize();
+}
+
+fn get_main_window(app: &Application, main_window: &Rc<RefCell<Option<MainWindow>>>) -> MainWindow {
+    main_window
+        .borrow_mut()
+        .get_or_insert_with(|| build_ui(app))
+        .clone()
+}
+
+fn build_ui(app: &Application) -> MainWindow {
     load_css();
 
     let app_state = Arc::new(Mutex::new(AppState::new()));
@@ -87,18 +449,97 @@ fn build_ui(app: &Application) {
         .spacing(5)
         .build();
 
+    // Build status bar
+    let status_label = gtk::Label::new(None);
+    status_label.set_halign(gtk::Align::Start);
+    status_label.set_hexpand(true);
+
+    let cancel_button = gtk::Button::with_label("Cancel");
+    cancel_button.set_visible(false);
+    cancel_button.connect_clicked(glib::clone!(
+        #[strong]
+        app_state,
+        move |_| {
+            if let Ok(app_state) = app_state.lock()
+                && let Some(cancel) = &app_state.scan_cancel
+            {
+                cancel.store(true, atomic::Ordering::Relaxed);
+            }
+        }
+    ));
+
+    let status_bar = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    status_bar.add_css_class("status-bar");
+    let cache_label = gtk::Label::new(None);
+    cache_label.add_css_class("dim-label");
+
+    status_bar.append(&status_label);
+    status_bar.append(&cache_label);
+    status_bar.append(&cancel_button);
+
+    glib::timeout_add_seconds_local(1, move || {
+        update_cache_label(&cache_label);
+        glib::ControlFlow::Continue
+    });
+
+    let problems = Problems::new();
+
     let app_ui = Rc::new(RefCell::new(AppUI {
         top_vbox: vbox.clone(),
+        status_label,
+        cancel_button,
+        thumbnail_loader: ThumbnailLoader::new(problems.clone()),
+        accordions: HashMap::new(),
+        dir_watcher: DirWatcher::new(problems.clone()),
+        config_watcher: None,
+        action_output: ActionOutput::new(),
+        actions_menu: gio::Menu::new(),
+        problems: problems.clone(),
     }));
 
+    app_ui.borrow().dir_watcher.connect_changes(glib::clone!(
+        #[strong]
+        app_state,
+        #[weak]
+        app_ui,
+        move |changes| apply_fs_changes(&app_state, &app_ui, changes)
+    ));
+
+    let config_watcher = ConfigWatcher::new(
+        problems.clone(),
+        glib::clone!(
+            #[strong]
+            app_state,
+            #[weak]
+            app_ui,
+            move || reload_app_config(&app_state, &app_ui)
+        ),
+    );
+    match config_watcher {
+        Ok(config_watcher) => app_ui.borrow_mut().config_watcher = Some(config_watcher),
+        Err(e) => problems.notify(&format!("Failed to watch the config file: {e}")),
+    }
+
     // Build menubar
     let menubar = gio::Menu::new();
 
     let file_menu = gio::Menu::new();
     file_menu.append(Some("Open Folder"), Some("app.open"));
+    file_menu.append(Some("Rescan"), Some("app.rescan"));
+    file_menu.append(Some("Hide Folder…"), Some("app.hide-folder"));
     file_menu.append(Some("Open Settings"), Some("app.settings"));
 
     menubar.append_submenu(Some("File"), &file_menu);
+    menubar.append_submenu(Some("Actions"), &app_ui.borrow().actions_menu);
+
+    if let Ok(app_config) = APP_CONFIG.read() {
+        custom_action::install(
+            app,
+            &app_ui.borrow().actions_menu,
+            &app_config.actions,
+            &problems,
+        );
+    }
 
     app.set_menubar(Some(&menubar));
     window.set_show_menubar(true);
@@ -111,12 +552,41 @@ fn build_ui(app: &Application) {
         .hscrollbar_policy(gtk::PolicyType::Never)
         .vscrollbar_policy(gtk::PolicyType::Automatic)
         .child(&vbox)
+        .vexpand(true)
         .build();
 
-    window.set_child(Some(&scrollable_window));
+    let thumbnail_loader = app_ui.borrow().thumbnail_loader.clone();
+    scrollable_window
+        .vadjustment()
+        .connect_value_changed(move |_| thumbnail_loader.queue_reprioritize());
+
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&scrollable_window);
+    content.append(&app_ui.borrow().action_output.widget);
+    content.append(&problems.widget);
+    content.append(&status_bar);
 
-    // Finalize
-    window.present();
+    window.set_child(Some(&content));
+
+    // Shown after the window itself has been presented
+    glib::idle_add_local_once(glib::clone!(
+        #[weak]
+        window,
+        #[strong]
+        problems,
+        move || {
+            report_config_problem(&window);
+            // The cache reads its budget on first use, which may not have happened yet
+            LazyLock::force(&IMAGE_CACHE);
+            show_config_warnings(&problems);
+        }
+    ));
+
+    MainWindow {
+        window,
+        app_state,
+        app_ui,
+    }
 }
 
 fn build_action(
@@ -125,13 +595,14 @@ fn build_action(
     app_ui: &Rc<RefCell<AppUI>>,
     app_state: &Arc<Mutex<AppState>>,
 ) {
-    let app_ui = app_ui.clone();
-    let app_state = app_state.clone();
-
     let open_action = gio::SimpleAction::new("open", None);
     open_action.connect_activate(glib::clone!(
         #[weak]
         window,
+        #[strong]
+        app_ui,
+        #[strong]
+        app_state,
         move |_, _| {
             let dialog = FileDialog::new();
             let cancellable = Cancellable::new();
@@ -141,53 +612,339 @@ fn build_action(
                 if let Ok(path) = result
                     && let Some(dir) = path.path()
                 {
-                    let mut app_state_guard = app_state.lock().unwrap();
-                    app_state_guard.original_dir = dir.to_str().unwrap().to_string();
-                    let app_state = app_state.clone();
-                    glib::spawn_future_local(async move {
-                        update_entry(app_state.clone(), &app_ui.borrow().top_vbox)
-                            .expect("Failed to update entry");
-                    });
+                    open_path(&app_state, &app_ui, &dir);
                 }
             });
         }
     ));
     app.add_action(&open_action);
 
+    let rescan_action = gio::SimpleAction::new("rescan", None);
+    rescan_action.connect_activate(glib::clone!(
+        #[strong]
+        app_ui,
+        #[strong]
+        app_state,
+        move |_, _| {
+            let problems = app_ui.borrow().problems.clone();
+            if let Err(e) = rescan(app_state.clone(), app_ui.clone()) {
+                problems.notify(&format!("Failed to rescan the folder: {e}"));
+            }
+        }
+    ));
+    app.add_action(&rescan_action);
+    app.set_accels_for_action("app.rescan", &["F5"]);
+
+    let hide_folder_action = gio::SimpleAction::new("hide-folder", None);
+    hide_folder_action.connect_activate(glib::clone!(
+        #[weak]
+        window,
+        #[strong]
+        app_ui,
+        #[strong]
+        app_state,
+        move |_, _| {
+            let original_dir = match app_state.lock() {
+                Ok(app_state) if !app_state.original_dir.is_empty() => {
+                    app_state.original_dir.clone()
+                }
+                _ => return,
+            };
+            // Starts from the folder of the first selected image, which is most likely the one to hide
+            let initial_folder = selected_paths(&app_state, &app_ui)
+                .first()
+                .and_then(|path| Path::new(path).parent().map(Path::to_path_buf))
+                .unwrap_or_else(|| original_dir.into());
+
+            let dialog = FileDialog::new();
+            dialog.set_title("Hide Folder");
+            dialog.set_initial_folder(Some(&gio::File::for_path(initial_folder)));
+            let cancellable = Cancellable::new();
+            let app_ui = app_ui.clone();
+            let app_state = app_state.clone();
+            dialog.select_folder(Some(&window), Some(&cancellable), move |result| {
+                if let Ok(path) = result
+                    && let Some(dir) = path.path()
+                    && let Err(e) = hide_folder(&app_state, &app_ui, &dir)
+                {
+                    let problems = app_ui.borrow().problems.clone();
+                    problems.notify(&format!("Failed to hide {}: {e}", dir.display()));
+                }
+            });
+        }
+    ));
+    app.add_action(&hide_folder_action);
+
     let settings_action = gio::SimpleAction::new("settings", None);
     settings_action.connect_activate(glib::clone!(
         #[weak]
         window,
+        #[strong]
+        app_ui,
+        #[strong]
+        app_state,
         move |_, _| {
-            let settings_window = SettingsWindow::new(&window);
+            let app_ui = app_ui.clone();
+            let app_state = app_state.clone();
+            let settings_window = SettingsWindow::new(&window, move |stored, saved| {
+                // Command line values stay for the session unless that field was changed
+                let mut app_config = saved.clone();
+                if let Ok(mut overrides) = CONFIG_OVERRIDES.lock() {
+                    overrides.forget_changed(stored, saved);
+                    overrides.apply(&mut app_config);
+                }
+
+                let previous = match APP_CONFIG.write() {
+                    Ok(mut current) => std::mem::replace(&mut *current, app_config.clone()),
+                    Err(_) => return,
+                };
+                apply_config_changes(&app_state, &app_ui, &previous, &app_config);
+            });
             match settings_window {
                 Ok(settings_window) => {
                     settings_window.show();
                 }
                 Err(e) => {
-                    eprintln!("Failed to create settings window: {e}");
+                    let problems = app_ui.borrow().problems.clone();
+                    problems.notify(&format!("Failed to open the settings: {e}"));
                 }
             }
         }
     ));
     app.add_action(&settings_action);
+
+    // From the menubar or a key, on every selected image
+    let run_action = gio::SimpleAction::new(RUN_ACTION, Some(glib::VariantTy::UINT32));
+    run_action.connect_activate(glib::clone!(
+        #[weak]
+        app,
+        #[strong]
+        app_state,
+        #[strong]
+        app_ui,
+        move |_, parameter| {
+            if let Some(index) = parameter.and_then(|parameter| parameter.get::<u32>()) {
+                let paths = selected_paths(&app_state, &app_ui);
+                run_custom_action(&app, &app_ui, index, paths);
+            }
+        }
+    ));
+    app.add_action(&run_action);
+
+    // From the context menu of a thumbnail, which passes the images itself
+    let run_action_on = gio::SimpleAction::new(
+        RUN_ACTION_ON,
+        Some(&<(u32, Vec<String>)>::static_variant_type()),
+    );
+    run_action_on.connect_activate(glib::clone!(
+        #[weak]
+        app,
+        #[strong]
+        app_ui,
+        move |_, parameter| {
+            if let Some((index, paths)) =
+                parameter.and_then(|parameter| parameter.get::<(u32, Vec<String>)>())
+            {
+                run_custom_action(&app, &app_ui, index, paths);
+            }
+        }
+    ));
+    app.add_action(&run_action_on);
 }
 
-fn update_entry(app_state: Arc<Mutex<AppState>>, vbox: &gtk::Box) -> anyhow::Result<()> {
-    clear_ui(vbox);
+// Writes an ignore rule for `dir` and drops its sections right away instead of waiting for the watcher
+fn hide_folder(
+    app_state: &Arc<Mutex<AppState>>,
+    app_ui: &Rc<RefCell<AppUI>>,
+    dir: &Path,
+) -> anyhow::Result<()> {
+    let original_dir = app_state
+        .lock()
+        .map_err(|_| anyhow!("Failed to lock app state"))?
+        .original_dir
+        .clone();
+    if dir == Path::new(&original_dir) || !dir.starts_with(&original_dir) {
+        return Err(anyhow!("only folders inside {original_dir} can be hidden"));
+    }
+
+    folder_config::hide(dir)?;
 
-    let (original_dir, entries_indies) = search_and_prepare_entries(app_state.clone())?;
+    let dir = dir.to_string_lossy();
+    app_ui.borrow().dir_watcher.unwatch(&dir);
+    remove_dir_sections(app_state, app_ui, &dir);
+    Ok(())
+}
 
-    for (index, entry) in entries_indies.iter().enumerate() {
-        create_blank_accordion_widget(
-            vbox,
-            entry.image_entries.len(),
-            &get_relative_path(&original_dir, &entry.dir_path)?,
-            index,
-            app_state.clone(),
-        )?;
+fn run_custom_action(
+    app: &Application,
+    app_ui: &Rc<RefCell<AppUI>>,
+    index: u32,
+    paths: Vec<String>,
+) {
+    let action = match APP_CONFIG.read() {
+        Ok(app_config) => app_config.actions.get(index as usize).cloned(),
+        Err(_) => return,
+    };
+    let Some(action) = action else {
+        return;
+    };
+
+    let output = app_ui.borrow().action_output.clone();
+    glib::spawn_future_local(custom_action::run(
+        action,
+        paths,
+        output,
+        app.active_window(),
+    ));
+}
+
+// Selected images of every section, in the order the sections are shown
+fn selected_paths(app_state: &Arc<Mutex<AppState>>, app_ui: &Rc<RefCell<AppUI>>) -> Vec<String> {
+    let dir_paths: Vec<String> = match app_state.lock() {
+        Ok(app_state) => app_state
+            .dir_entries
+            .iter()
+            .map(|dir_entry| dir_entry.dir_path.clone())
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+
+    let app_ui = app_ui.borrow();
+    dir_paths
+        .iter()
+        .filter_map(|dir_path| app_ui.accordions.get(dir_path))
+        .flat_map(|accordion_widget| accordion_widget.borrow().selected_paths())
+        .collect()
+}
+
+// Opens a folder, or the folder of an image with that image focused
+fn open_path(app_state: &Arc<Mutex<AppState>>, app_ui: &Rc<RefCell<AppUI>>, path: &Path) {
+    let problems = app_ui.borrow().problems.clone();
+    if !path.exists() {
+        problems.notify(&format!("No such file or directory: {}", path.display()));
+        return;
     }
 
+    let (dir, focus_path) = if path.is_dir() {
+        (path, None)
+    } else {
+        match path.parent() {
+            Some(parent) => (parent, Some(path.to_string_lossy().to_string())),
+            None => return,
+        }
+    };
+
+    let dir = match std::path::absolute(dir) {
+        Ok(dir) => dir,
+        Err(e) => {
+            problems.notify(&format!("Failed to resolve {}: {e}", dir.display()));
+            return;
+        }
+    };
+
+    if let Ok(mut app_state) = app_state.lock() {
+        app_state.original_dir = dir.to_string_lossy().to_string();
+        app_state.focus_path = focus_path;
+    }
+
+    spawn_update_entry(app_state, app_ui);
+}
+
+// Starts a scan of the opened folder from the main loop
+fn spawn_update_entry(app_state: &Arc<Mutex<AppState>>, app_ui: &Rc<RefCell<AppUI>>) {
+    let app_state = app_state.clone();
+    let app_ui = app_ui.clone();
+    glib::spawn_future_local(async move {
+        let problems = app_ui.borrow().problems.clone();
+        if let Err(e) = update_entry(app_state, app_ui) {
+            problems.notify(&format!("Failed to open the folder: {e}"));
+        }
+    });
+}
+
+fn update_entry(app_state: Arc<Mutex<AppState>>, app_ui: Rc<RefCell<AppUI>>) -> anyhow::Result<()> {
+    clear_ui(&app_ui.borrow().top_vbox);
+    app_ui.borrow_mut().accordions.clear();
+    app_ui.borrow().problems.clear();
+
+    let original_dir = {
+        let mut app_state_guard = app_state.lock().map_err(|_| anyhow!("Failed to lock"))?;
+        app_state_guard.dir_entries.clear();
+        app_state_guard.original_dir.clone()
+    };
+
+    let max_depth = {
+        let app_config = APP_CONFIG
+            .read()
+            .map_err(|_| anyhow!("Failed to lock app config"))?;
+        app_config.max_depth
+    };
+    app_ui.borrow().dir_watcher.reset(&original_dir, max_depth);
+
+    start_scan(app_state, app_ui, ScanMode::Open)
+}
+
+// Scans the opened folder again and only touches the sections that changed, so expanded sections,
+// the scroll position and loaded thumbnails stay as they are
+fn rescan(app_state: Arc<Mutex<AppState>>, app_ui: Rc<RefCell<AppUI>>) -> anyhow::Result<()> {
+    let is_open = !app_state
+        .lock()
+        .map_err(|_| anyhow!("Failed to lock"))?
+        .original_dir
+        .is_empty();
+    if !is_open {
+        return Ok(());
+    }
+
+    // Folders and settings that are still broken are reported again by the scan
+    {
+        let problems = app_ui.borrow().problems.clone();
+        problems.clear_kind(ProblemKind::Read);
+        problems.clear_kind(ProblemKind::Settings);
+    }
+
+    start_scan(app_state, app_ui, ScanMode::Rescan)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanMode {
+    // Sections are added as the scan reports them
+    Open,
+    // The result is compared with the sections shown once the scan is done
+    Rescan,
+}
+
+fn start_scan(
+    app_state: Arc<Mutex<AppState>>,
+    app_ui: Rc<RefCell<AppUI>>,
+    mode: ScanMode,
+) -> anyhow::Result<()> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let original_dir = {
+        let mut app_state_guard = app_state.lock().map_err(|_| anyhow!("Failed to lock"))?;
+
+        // Stop the previous scan before anything of it can reach the new state
+        if let Some(previous) = app_state_guard.scan_cancel.replace(cancel.clone()) {
+            previous.store(true, atomic::Ordering::Relaxed);
+        }
+
+        app_state_guard.original_dir.clone()
+    };
+
+    let (tx, rx) = mpsc::channel::<ScanEvent>();
+    spawn_scan_thread(original_dir.clone(), cancel.clone(), tx);
+
+    set_scan_status(&app_ui, "Scanning...", true);
+
+    glib::spawn_future_local(receive_scan_events(
+        app_state,
+        app_ui,
+        rx,
+        cancel,
+        original_dir,
+        mode,
+    ));
+
     Ok(())
 }
 
@@ -197,252 +954,799 @@ fn clear_ui(vbox: &gtk::Box) {
     }
 }
 
-fn search_and_prepare_entries(
+fn spawn_scan_thread(root: String, cancel: Arc<AtomicBool>, tx: mpsc::Sender<ScanEvent>) {
+    thread::spawn(move || {
+        let result = entry::DirEntry::search(&root, &cancel, |event| {
+            let _ = tx.send(event);
+        });
+
+        if let Err(e) = result {
+            let _ = tx.send(ScanEvent::Unreadable {
+                path: root,
+                reason: format!("{e:#}"),
+            });
+        }
+    });
+}
+
+async fn receive_scan_events(
     app_state: Arc<Mutex<AppState>>,
-) -> anyhow::Result<(String, Vec<entry::DirEntry>)> {
-    let dir_path = {
-        let app_state_guard = app_state.lock().map_err(|_| anyhow!("Failed to lock"))?;
-        app_state_guard.original_dir.clone()
+    app_ui: Rc<RefCell<AppUI>>,
+    rx: mpsc::Receiver<ScanEvent>,
+    cancel: Arc<AtomicBool>,
+    original_dir: String,
+    mode: ScanMode,
+) {
+    let (mut dirs, mut images) = (0, 0);
+    // Only kept for a rescan
+    let mut scanned = Vec::new();
+    let mut visited = HashSet::new();
+
+    loop {
+        if !is_current_scan(&app_state, &cancel) {
+            return;
+        }
+        if cancel.load(atomic::Ordering::Relaxed) {
+            set_scan_status(
+                &app_ui,
+                &format!("Scan cancelled: scanned {dirs} dirs / {images} images"),
+                false,
+            );
+            return;
+        }
+
+        let drained = scan_queue::drain(&rx, SCAN_TICK_BUDGET, |event| match event {
+            ScanEvent::Progress {
+                dirs: scanned_dirs,
+                images: found_images,
+            } => {
+                dirs = scanned_dirs;
+                images = found_images;
+            }
+            ScanEvent::Visit(dir_path) => {
+                app_ui.borrow().dir_watcher.watch(&dir_path);
+                if mode == ScanMode::Rescan {
+                    visited.insert(dir_path);
+                }
+            }
+            ScanEvent::Unreadable { path, reason } => {
+                app_ui
+                    .borrow()
+                    .problems
+                    .report(ProblemKind::Read, &path, &reason, None::<fn()>);
+            }
+            ScanEvent::BrokenSettings { path, reason } => {
+                app_ui.borrow().problems.report(
+                    ProblemKind::Settings,
+                    &path,
+                    &reason,
+                    None::<fn()>,
+                );
+            }
+            ScanEvent::Dir(dir_entry) if mode == ScanMode::Rescan => {
+                scanned.push(dir_entry);
+            }
+            ScanEvent::Dir(dir_entry) => {
+                let dir_path = dir_entry.dir_path.clone();
+                if let Err(e) = add_dir_entry(&app_state, &app_ui, dir_entry, &original_dir) {
+                    let problems = app_ui.borrow().problems.clone();
+                    problems.notify(&format!("Failed to add {dir_path}: {e}"));
+                }
+                focus_pending_image(&app_state, &app_ui, &dir_path);
+            }
+        });
+
+        if drained == Drained::Finished {
+            break;
+        }
+
+        set_scan_status(
+            &app_ui,
+            &format!("Scanning... scanned {dirs} dirs / {images} images"),
+            true,
+        );
+        match drained {
+            // Lets the window draw what was added, then carries on right away
+            Drained::OutOfTime => {
+                glib::timeout_future_with_priority(glib::Priority::DEFAULT_IDLE, Duration::ZERO)
+                    .await;
+            }
+            _ => glib::timeout_future(SCAN_POLL_INTERVAL).await,
+        }
+    }
+
+    let modified = match mode {
+        ScanMode::Rescan => find_modified(&app_ui).await,
+        ScanMode::Open => HashSet::new(),
     };
+    // Another scan may have started in the meantime
+    if !is_current_scan(&app_state, &cancel) {
+        return;
+    }
 
-    let entries = entry::DirEntry::search(&dir_path)?;
+    if let Ok(mut app_state) = app_state.lock() {
+        app_state.scan_cancel = None;
+    }
+    if mode == ScanMode::Rescan {
+        apply_rescan(
+            &app_state,
+            &app_ui,
+            scanned,
+            &visited,
+            &modified,
+            &original_dir,
+        );
+    }
+    set_scan_status(
+        &app_ui,
+        &format!("Scanned {dirs} dirs / {images} images"),
+        false,
+    );
+}
 
-    let mut app_state_guard = app_state.lock().map_err(|_| anyhow!("Failed to lock"))?;
+// Removes the sections of directories the scan no longer found, adds the new ones and syncs the tiles of the rest
+fn apply_rescan(
+    app_state: &Arc<Mutex<AppState>>,
+    app_ui: &Rc<RefCell<AppUI>>,
+    dir_entries: Vec<entry::DirEntry>,
+    visited: &HashSet<String>,
+    modified: &HashSet<String>,
+    original_dir: &str,
+) {
+    app_ui.borrow().dir_watcher.retain(visited);
+
+    let scanned: HashSet<&str> = dir_entries
+        .iter()
+        .map(|dir_entry| dir_entry.dir_path.as_str())
+        .collect();
+    let removed: Vec<String> = match app_state.lock() {
+        Ok(app_state) => app_state
+            .dir_entries
+            .iter()
+            .filter(|dir_entry| !scanned.contains(dir_entry.dir_path.as_str()))
+            .map(|dir_entry| dir_entry.dir_path.clone())
+            .collect(),
+        Err(_) => return,
+    };
+    for dir_path in removed {
+        remove_dir_section(app_state, app_ui, &dir_path);
+    }
 
-    app_state_guard.dir_entries = entries;
-    app_state_guard
-        .dir_entries
-        .sort_by(|a, b| a.dir_path.cmp(&b.dir_path));
+    let problems = app_ui.borrow().problems.clone();
+    for dir_entry in dir_entries {
+        let dir_path = dir_entry.dir_path.clone();
+        if let Err(e) = sync_dir_section(app_state, app_ui, dir_entry, modified, original_dir) {
+            problems.notify(&format!("Failed to update {dir_path}: {e}"));
+        }
+    }
+}
 
-    let original_dir = app_state_guard.original_dir.clone();
-    let dir_entries = app_state_guard.dir_entries.clone();
+// Files whose modification time or size differs from when their thumbnail was decoded. Checked on a
+// thread of its own, since that is a stat for every thumbnail loaded so far
+async fn find_modified(app_ui: &Rc<RefCell<AppUI>>) -> HashSet<String> {
+    let loaded: Vec<CacheKey> = app_ui
+        .borrow()
+        .accordions
+        .values()
+        .flat_map(|accordion_widget| {
+            accordion_widget
+                .borrow()
+                .store
+                .iter::<ImageItem>()
+                .flatten()
+                .filter_map(|item| item.loaded_from())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let modified: HashSet<String> = loaded
+            .into_iter()
+            .filter(|key| CacheKey::new(&key.path, key.thumbnail_size).ok().as_ref() != Some(key))
+            .map(|key| key.path)
+            .collect();
+        let _ = tx.send(modified);
+    });
+    rx.await.unwrap_or_default()
+}
 
-    Ok((original_dir, dir_entries))
+fn is_current_scan(app_state: &Arc<Mutex<AppState>>, cancel: &Arc<AtomicBool>) -> bool {
+    match app_state.lock() {
+        Ok(app_state) => app_state
+            .scan_cancel
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, cancel)),
+        Err(_) => false,
+    }
 }
 
-fn create_blank_accordion_widget(
-    vbox: &gtk::Box,
-    count: usize,
+fn update_cache_label(cache_label: &gtk::Label) {
+    let stats = cache_stats();
+    cache_label.set_text(&format!(
+        "Cache {} / {} · {} hits / {} misses",
+        format_size(stats.used_bytes),
+        format_size(stats.budget_bytes),
+        stats.hits,
+        stats.misses
+    ));
+}
+
+fn set_scan_status(app_ui: &Rc<RefCell<AppUI>>, text: &str, scanning: bool) {
+    let app_ui = app_ui.borrow();
+    app_ui.status_label.set_text(text);
+    app_ui.cancel_button.set_visible(scanning);
+}
+
+fn add_dir_entry(
+    app_state: &Arc<Mutex<AppState>>,
+    app_ui: &Rc<RefCell<AppUI>>,
+    dir_entry: entry::DirEntry,
+    original_dir: &str,
+) -> anyhow::Result<()> {
+    let title = get_relative_path(original_dir, &dir_entry.dir_path)?;
+    let dir_path = dir_entry.dir_path.clone();
+    let details = dir_entry.settings.describe();
+    let expanded = dir_entry.settings.expanded;
+
+    // Keep the accordions in walk order, also for folders the watcher adds later. Paths compare by
+    // component, so `a-c` comes after everything inside `a` instead of between `a` and `a/b`
+    let position = {
+        let mut app_state_guard = app_state.lock().map_err(|_| anyhow!("Failed to lock"))?;
+        let position = match app_state_guard
+            .dir_entries
+            .binary_search_by(|e| Path::new(&e.dir_path).cmp(Path::new(&dir_entry.dir_path)))
+        {
+            // Already added by the watcher while the scan was still running
+            Ok(_) => return Ok(()),
+            Err(position) => position,
+        };
+        app_state_guard.dir_entries.insert(position, dir_entry);
+        position
+    };
+
+    let accordion_widget = create_accordion_widget(
+        &mut app_ui.borrow_mut(),
+        &title,
+        position,
+        dir_path,
+        app_state.clone(),
+    )?;
+    accordion_widget.borrow().set_details(&details);
+
+    // Expanding runs the expand handler, which needs to borrow the section itself
+    if expanded {
+        let expander = accordion_widget.borrow().expander.clone();
+        expander.set_expanded(true);
+    }
+
+    Ok(())
+}
+
+fn create_accordion_widget(
+    app_ui: &mut AppUI,
     title: &str,
-    index: usize,
+    position: usize,
+    dir_path: String,
     app_state: Arc<Mutex<AppState>>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Rc<RefCell<AccordionWidget>>> {
     let accordion_widget = Rc::new(RefCell::new(AccordionWidget::new(title)));
-    let mut overlays = Vec::new();
 
-    for _ in 0..count {
-        let thumbnail_size = {
-            let app_config = APP_CONFIG
-                .read()
-                .map_err(|_| anyhow!("Failed to lock app config"))?;
-            app_config.thumbnail_size
-        } as i32;
+    let vbox = &app_ui.top_vbox;
+    let sibling = position
+        .checked_sub(1)
+        .and_then(|index| nth_child(vbox, index));
+    vbox.insert_child_after(&accordion_widget.borrow().widget, sibling.as_ref());
 
-        let fixed_size_container = gtk::Box::new(gtk::Orientation::Vertical, 0);
-        fixed_size_container.set_size_request(thumbnail_size, thumbnail_size);
-        fixed_size_container.set_halign(gtk::Align::Center);
-        fixed_size_container.set_valign(gtk::Align::Center);
+    let thumbnail_loader = app_ui.thumbnail_loader.clone();
+    accordion_widget
+        .borrow()
+        .scrolled_window
+        .vadjustment()
+        .connect_value_changed(move |_| thumbnail_loader.queue_reprioritize());
+
+    app_ui
+        .accordions
+        .insert(dir_path.clone(), accordion_widget.clone());
+
+    setup_accordion_expand_handler(
+        dir_path,
+        accordion_widget.clone(),
+        app_state,
+        app_ui.thumbnail_loader.clone(),
+    );
 
-        let overlay = gtk::Overlay::new();
-        overlay.set_child(Some(&fixed_size_container));
+    Ok(accordion_widget)
+}
 
-        accordion_widget.borrow().flow_box.append(&overlay);
-        overlays.push(overlay);
-    }
+// Expands the section of the image that was opened and scrolls that image into view
+fn focus_pending_image(
+    app_state: &Arc<Mutex<AppState>>,
+    app_ui: &Rc<RefCell<AppUI>>,
+    dir_path: &str,
+) {
+    let focus_path = match app_state.lock() {
+        Ok(mut app_state) => {
+            let in_dir = app_state
+                .focus_path
+                .as_deref()
+                .and_then(|path| Path::new(path).parent())
+                .is_some_and(|parent| parent == Path::new(dir_path));
+            if !in_dir {
+                return;
+            }
+            app_state.focus_path.take()
+        }
+        Err(_) => return,
+    };
+    let Some(focus_path) = focus_path else {
+        return;
+    };
+    let Some(accordion_widget) = app_ui.borrow().accordions.get(dir_path).cloned() else {
+        return;
+    };
 
-    vbox.append(&accordion_widget.borrow().widget);
+    // Expanding runs the expand handler, which needs to borrow the section itself
+    let expander = accordion_widget.borrow().expander.clone();
+    expander.set_expanded(true);
 
-    setup_accordion_expand_handler(index, accordion_widget, overlays, app_state);
+    let accordion_widget = accordion_widget.borrow();
+    let position = accordion_widget
+        .store
+        .iter::<ImageItem>()
+        .flatten()
+        .position(|item| Path::new(&item.path()) == Path::new(&focus_path));
+    if let Some(position) = position {
+        accordion_widget
+            .grid_view
+            .scroll_to(position as u32, gtk::ListScrollFlags::FOCUS, None);
+    }
+}
 
-    Ok(())
+fn nth_child(vbox: &gtk::Box, index: usize) -> Option<gtk::Widget> {
+    let mut child = vbox.first_child();
+    for _ in 0..index {
+        child = child?.next_sibling();
+    }
+    child
 }
 
 fn setup_accordion_expand_handler(
-    index: usize,
+    dir_path: String,
     accordion_widget: Rc<RefCell<AccordionWidget>>,
-    overlays: Vec<gtk::Overlay>,
     app_state: Arc<Mutex<AppState>>,
+    thumbnail_loader: ThumbnailLoader,
 ) {
     accordion_widget
         .clone()
         .borrow()
         .connect_expanded(move |is_expanded| {
             if is_expanded {
-                let app_state_clone = app_state.clone();
-                let accordion_widget = accordion_widget.clone();
-                let overlays = overlays.clone();
-
-                prepare_accordion_for_loading(&accordion_widget);
+                if let Err(e) =
+                    refresh_accordion(&app_state, &accordion_widget, &dir_path, &thumbnail_loader)
+                {
+                    thumbnail_loader
+                        .problems()
+                        .notify(&format!("Failed to refresh {dir_path}: {e}"));
+                }
 
-                glib::spawn_future_local(async move {
-                    load_and_display_images(app_state_clone, accordion_widget, overlays, index)
-                        .await;
-                });
+                let accordion_widget = accordion_widget.borrow();
+                thumbnail_loader.prefetch(&accordion_widget.store, &accordion_widget.progress);
+            } else {
+                // Nothing of a collapsed section is visible, so its queued thumbnails are dropped
+                thumbnail_loader.cancel(&accordion_widget.borrow().progress);
             }
+            thumbnail_loader.queue_reprioritize();
         });
 }
 
-fn prepare_accordion_for_loading(accordion_widget: &Rc<RefCell<AccordionWidget>>) {
-    let accordion_widget = accordion_widget.borrow();
+// Sections keep their tiles while collapsed and are only rebuilt when what they show has changed
+fn refresh_accordion(
+    app_state: &Arc<Mutex<AppState>>,
+    accordion_widget: &Rc<RefCell<AccordionWidget>>,
+    dir_path: &str,
+    thumbnail_loader: &ThumbnailLoader,
+) -> anyhow::Result<()> {
+    let settings = dir_settings(app_state, dir_path)?;
+    let snapshot = SectionSnapshot::new(dir_path, &settings);
+    let previous = accordion_widget.borrow().snapshot.clone();
+
+    if previous.as_ref() == Some(&snapshot) {
+        return Ok(());
+    }
 
-    while let Some(child) = accordion_widget.flow_box.first_child() {
-        accordion_widget.flow_box.remove(&child);
+    if let Some(previous) = &previous
+        && previous.modified != snapshot.modified
+    {
+        let image_entries = entry::DirEntry::list_images(dir_path, &settings)?;
+        let mut app_state_guard = app_state.lock().map_err(|_| anyhow!("Failed to lock"))?;
+        if let Some(dir_entry) = app_state_guard
+            .dir_entries
+            .iter_mut()
+            .find(|e| e.dir_path == dir_path)
+        {
+            dir_entry.image_entries = image_entries;
+        }
+    }
+
+    let resized = previous
+        .as_ref()
+        .is_none_or(|previous| previous.thumbnail_size != snapshot.thumbnail_size);
+    let command_changed = previous
+        .as_ref()
+        .is_none_or(|previous| previous.open_command != snapshot.open_command);
+    if resized || command_changed {
+        let accordion_widget = accordion_widget.borrow();
+        accordion_widget.set_factory(&ImageWidget::factory(
+            settings.thumbnail_size as i32,
+            settings.open_command.clone(),
+            thumbnail_loader,
+            &accordion_widget.progress,
+        ));
     }
 
-    accordion_widget.progress_bar.set_fraction(0.0);
-    accordion_widget.progress_bar.set_visible(true);
+    populate_accordion(app_state, accordion_widget, dir_path, !resized)?;
+    accordion_widget.borrow_mut().snapshot = Some(snapshot);
+
+    Ok(())
 }
 
-async fn load_and_display_images(
-    app_state: Arc<Mutex<AppState>>,
-    accordion_widget: Rc<RefCell<AccordionWidget>>,
-    overlays: Vec<gtk::Overlay>,
-    index: usize,
+fn dir_settings(
+    app_state: &Arc<Mutex<AppState>>,
+    dir_path: &str,
+) -> anyhow::Result<FolderSettings> {
+    let app_state = app_state.lock().map_err(|_| anyhow!("Failed to lock"))?;
+    app_state
+        .dir_entries
+        .iter()
+        .find(|e| e.dir_path == dir_path)
+        .map(|dir_entry| dir_entry.settings.clone())
+        .ok_or_else(|| anyhow!("Unknown directory: {dir_path}"))
+}
+
+// The scanner already sorted the images, so every tile starts out in its final slot
+fn populate_accordion(
+    app_state: &Arc<Mutex<AppState>>,
+    accordion_widget: &Rc<RefCell<AccordionWidget>>,
+    dir_path: &str,
+    reuse_items: bool,
+) -> anyhow::Result<()> {
+    let (image_paths, thumbnail_size) = {
+        let app = app_state.lock().map_err(|_| anyhow!("Failed to lock"))?;
+        let dir_entry = app
+            .dir_entries
+            .iter()
+            .find(|e| e.dir_path == dir_path)
+            .ok_or_else(|| anyhow!("Unknown directory: {dir_path}"))?;
+        (
+            dir_entry
+                .image_entries
+                .iter()
+                .map(|image_entry| image_entry.image_path.clone())
+                .collect::<Vec<_>>(),
+            dir_entry.settings.thumbnail_size,
+        )
+    };
+
+    let accordion_widget = accordion_widget.borrow();
+    let store = &accordion_widget.store;
+
+    // Tiles of files that are still there keep the thumbnails they already have
+    let mut existing: HashMap<String, ImageItem> = if reuse_items {
+        store
+            .iter::<ImageItem>()
+            .flatten()
+            .map(|item| (item.path(), item))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let items: Vec<ImageItem> = image_paths
+        .iter()
+        .map(|image_path| {
+            existing
+                .remove(image_path)
+                .unwrap_or_else(|| ImageItem::new(image_path, thumbnail_size))
+        })
+        .collect();
+
+    clear_cache();
+
+    store.splice(0, store.n_items(), &items);
+    Ok(())
+}
+
+// Brings the sections of every changed directory in line with what is on disk now
+fn apply_fs_changes(
+    app_state: &Arc<Mutex<AppState>>,
+    app_ui: &Rc<RefCell<AppUI>>,
+    changes: FsChanges,
 ) {
-    let dir_entry_clone = {
-        match app_state.lock() {
-            Ok(app) => {
-                if index >= app.dir_entries.len() {
-                    eprintln!("Invalid index: {index}");
-                    return;
-                }
+    let original_dir = match app_state.lock() {
+        Ok(app_state) => app_state.original_dir.clone(),
+        Err(e) => {
+            app_ui
+                .borrow()
+                .problems
+                .notify(&format!("Failed to apply folder changes: {e}"));
+            return;
+        }
+    };
+    let dir_watcher = app_ui.borrow().dir_watcher.clone();
+
+    let mut dirs: Vec<String> = changes.dirs.iter().cloned().collect();
+
+    // A changed `.gridx2.toml` or ignore file affects every section below its folder
+    for path in changes.dirs.iter().map(Path::new) {
+        if folder_config::is_settings_file(path)
+            && let Some(parent) = path.parent()
+        {
+            // Reported again below if it is still broken
+            app_ui
+                .borrow()
+                .problems
+                .resolve(ProblemKind::Settings, &path.to_string_lossy());
+            dirs.extend(
+                app_ui
+                    .borrow()
+                    .accordions
+                    .keys()
+                    .filter(|dir_path| Path::new(dir_path).starts_with(parent))
+                    .cloned(),
+            );
+            // Folders a removed rule no longer hides have no section yet
+            if let Ok(read_dir) = fs::read_dir(parent) {
+                dirs.extend(
+                    read_dir
+                        .flatten()
+                        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                        .map(|e| e.path().to_string_lossy().to_string()),
+                );
+            }
+        }
+    }
+    dirs.sort();
+    dirs.dedup();
+
+    while let Some(dir) = dirs.pop() {
+        if !Path::new(&dir).is_dir() {
+            dir_watcher.unwatch(&dir);
+            remove_dir_sections(app_state, app_ui, &dir);
+            continue;
+        }
+        if !dir_watcher.is_within_depth(&dir) {
+            continue;
+        }
 
-                app.dir_entries.clone()[index].clone()
+        let problems = app_ui.borrow().problems.clone();
+        let settings = match FolderSettings::resolve(&original_dir, &dir) {
+            Ok(Some(settings)) => settings,
+            // Ignored by a `.gridx2.toml` or ignore file further up, so not watched either
+            Ok(None) => {
+                dir_watcher.unwatch(&dir);
+                remove_dir_sections(app_state, app_ui, &dir);
+                continue;
             }
             Err(e) => {
-                eprintln!("Failed to lock app state: {e}");
-                return;
+                problems.notify(&format!("Failed to update {dir}: {e}"));
+                continue;
             }
+        };
+        for (path, reason) in &settings.problems {
+            problems.report(
+                ProblemKind::Settings,
+                &path.to_string_lossy(),
+                reason,
+                None::<fn()>,
+            );
         }
-    };
 
-    let total_images = dir_entry_clone.image_entries.len();
-    let counter = Arc::new(Mutex::new(0f64));
+        // A directory that was created or moved in may already contain subdirectories
+        if !dir_watcher.is_watched(&dir) {
+            dir_watcher.watch(&dir);
+            if let Ok(read_dir) = fs::read_dir(&dir) {
+                dirs.extend(
+                    read_dir
+                        .flatten()
+                        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                        .map(|e| e.path().to_string_lossy().to_string()),
+                );
+            }
+        }
 
-    let (tx, rx) = mpsc::channel::<f64>();
-    let (done_tx, done_rx) = mpsc::channel::<Vec<ImageEntry>>();
-    let (done_tx_check, done_rx_check) = mpsc::channel::<u8>();
+        let result = entry::DirEntry::list_images(&dir, &settings).and_then(|image_entries| {
+            let dir_entry = entry::DirEntry {
+                dir_path: dir.clone(),
+                image_entries,
+                settings,
+            };
+            sync_dir_section(
+                app_state,
+                app_ui,
+                dir_entry,
+                &changes.modified,
+                &original_dir,
+            )
+        });
+        if let Err(e) = result {
+            problems.notify(&format!("Failed to update {dir}: {e}"));
+        }
+    }
+}
 
-    let accordion_widget_cloned = accordion_widget.clone();
-    let loaded_entry = dir_entry_clone;
-    let loaded_entry_clone = loaded_entry.clone();
+// Updates the section of one directory to its current images, only adding and removing the tiles that changed
+fn sync_dir_section(
+    app_state: &Arc<Mutex<AppState>>,
+    app_ui: &Rc<RefCell<AppUI>>,
+    dir_entry: entry::DirEntry,
+    modified: &HashSet<String>,
+    original_dir: &str,
+) -> anyhow::Result<()> {
+    let accordion_widget = app_ui.borrow().accordions.get(&dir_entry.dir_path).cloned();
 
-    spawn_image_loading_thread(
-        &loaded_entry_clone,
-        counter,
-        total_images,
-        tx,
-        done_tx,
-        done_tx_check,
-    );
+    let Some(accordion_widget) = accordion_widget else {
+        if dir_entry.image_entries.is_empty() {
+            return Ok(());
+        }
+        return add_dir_entry(app_state, app_ui, dir_entry, original_dir);
+    };
 
-    update_progress_bar(accordion_widget_cloned.clone(), rx, done_rx_check).await;
+    let entry::DirEntry {
+        dir_path,
+        image_entries,
+        settings,
+    } = dir_entry;
+    let dir_path = dir_path.as_str();
+
+    // Sections of subdirectories stay, they have images of their own
+    if image_entries.is_empty() {
+        remove_dir_section(app_state, app_ui, dir_path);
+        return Ok(());
+    }
 
-    display_loaded_images(done_rx, accordion_widget_cloned, overlays).await;
-}
+    let image_paths: Vec<String> = image_entries
+        .iter()
+        .map(|image_entry| image_entry.image_path.clone())
+        .collect();
+    {
+        let mut app_state_guard = app_state.lock().map_err(|_| anyhow!("Failed to lock"))?;
+        if let Some(dir_entry) = app_state_guard
+            .dir_entries
+            .iter_mut()
+            .find(|e| e.dir_path == dir_path)
+        {
+            dir_entry.image_entries = image_entries;
+            dir_entry.settings = settings.clone();
+        }
+    }
+    accordion_widget.borrow().set_details(&settings.describe());
 
-fn spawn_image_loading_thread(
-    loaded_entry_clone: &entry::DirEntry,
-    counter: Arc<Mutex<f64>>,
-    total_images: usize,
-    tx: mpsc::Sender<f64>,
-    done_tx: mpsc::Sender<Vec<ImageEntry>>,
-    done_tx_check: mpsc::Sender<u8>,
-) {
-    let mut loaded_entry_clone = loaded_entry_clone.clone();
+    // Sections that were never expanded are filled from the new listing once they are
+    let Some(snapshot) = accordion_widget.borrow().snapshot.clone() else {
+        return Ok(());
+    };
 
-    thread::spawn(move || {
-        clear_cache();
-
-        loaded_entry_clone
-            .image_entries
-            .par_iter_mut()
-            .for_each(|image_entry| {
-                if let Err(e) = image_entry.load_image() {
-                    eprintln!("Failed to load image: {e}");
-                }
+    let thumbnail_loader = app_ui.borrow().thumbnail_loader.clone();
+    if snapshot.thumbnail_size != settings.thumbnail_size
+        || snapshot.open_command != settings.open_command
+    {
+        return refresh_accordion(app_state, &accordion_widget, dir_path, &thumbnail_loader);
+    }
 
-                let mut counter = match counter.lock() {
-                    Ok(counter) => counter,
-                    Err(e) => {
-                        eprintln!("Failed to lock counter: {e}");
-                        return;
-                    }
-                };
+    let mut accordion_widget = accordion_widget.borrow_mut();
+    sync_accordion_items(
+        &accordion_widget.store,
+        &image_paths,
+        settings.thumbnail_size,
+    );
 
-                *counter += 1.0;
+    for item in accordion_widget.store.iter::<ImageItem>().flatten() {
+        if modified.contains(&item.path()) {
+            thumbnail_loader.reload(&item, &accordion_widget.progress);
+        }
+    }
+    thumbnail_loader.queue_reprioritize();
 
-                let progress = *counter / total_images as f64;
-                let _ = tx.send(progress);
-            });
+    accordion_widget.snapshot = Some(SectionSnapshot::new(dir_path, &settings));
 
-        show_cache_stats();
-        let _ = done_tx.send(loaded_entry_clone.image_entries.clone());
-        let _ = done_tx_check.send(0);
-    });
+    Ok(())
 }
 
-async fn update_progress_bar(
-    accordion_widget: Rc<RefCell<AccordionWidget>>,
-    rx: mpsc::Receiver<f64>,
-    done_rx_check: mpsc::Receiver<u8>,
-) {
-    while let Ok(progress) = rx.recv() {
-        if done_rx_check.try_recv().is_ok() {
-            accordion_widget.borrow().progress_bar.set_fraction(1.0);
-            break;
+// Removes the tiles of deleted files and inserts new ones in their sorted slot, leaving every other tile alone
+fn sync_accordion_items(store: &gio::ListStore, image_paths: &[String], thumbnail_size: u32) {
+    let positions: HashMap<&str, usize> = image_paths
+        .iter()
+        .enumerate()
+        .map(|(position, image_path)| (image_path.as_str(), position))
+        .collect();
+    for position in (0..store.n_items()).rev() {
+        if let Some(item) = store.item(position).and_downcast::<ImageItem>()
+            && !positions.contains_key(item.path().as_str())
+        {
+            store.remove(position);
         }
-        accordion_widget
-            .borrow()
-            .progress_bar
-            .set_fraction(progress);
-        glib::timeout_future(Duration::from_millis(1)).await;
     }
-}
 
-async fn display_loaded_images(
-    done_rx: mpsc::Receiver<Vec<ImageEntry>>,
-    accordion_widget: Rc<RefCell<AccordionWidget>>,
-    overlays: Vec<gtk::Overlay>,
-) {
-    let image_entries = match done_rx.recv() {
-        Ok(image_entries) => {
-            let mut sorted_entries = image_entries.clone();
-            sorted_entries.sort_by(|a, b| {
-                natural_sort(a.image_path.as_str(), b.image_path.as_str())
-                    .unwrap_or(Ordering::Equal)
-            });
-            sorted_entries
-        }
-        Err(e) => {
-            eprintln!("Failed to receive image entries: {e}");
-            return;
-        }
-    };
+    // Sorting by modification time can move a rewritten file, then the tiles are put in order in one go
+    let in_order = store
+        .iter::<ImageItem>()
+        .flatten()
+        .map(|item| positions.get(item.path().as_str()).copied())
+        .is_sorted();
+    if !in_order {
+        let mut existing: HashMap<String, ImageItem> = store
+            .iter::<ImageItem>()
+            .flatten()
+            .map(|item| (item.path(), item))
+            .collect();
+        let items: Vec<ImageItem> = image_paths
+            .iter()
+            .map(|image_path| {
+                existing
+                    .remove(image_path)
+                    .unwrap_or_else(|| ImageItem::new(image_path, thumbnail_size))
+            })
+            .collect();
+        store.splice(0, store.n_items(), &items);
+        return;
+    }
 
-    for (index, image_entry) in image_entries.iter().enumerate() {
-        if let Some(img) = &image_entry.image {
-            let mut image_widget = ImageWidget::new();
-            image_widget.set_image(&image_entry.image_path, img.as_ref());
+    for (position, image_path) in image_paths.iter().enumerate() {
+        let position = position as u32;
+        let existing = store.item(position).and_downcast::<ImageItem>();
+        if existing.is_none_or(|item| &item.path() != image_path) {
+            store.insert(position, &ImageItem::new(image_path, thumbnail_size));
+        }
+    }
+}
 
-            let accordion_widget = accordion_widget.clone();
-            let overlays = overlays.clone();
+// Drops the section of `dir_path` and of every directory below it
+fn remove_dir_sections(
+    app_state: &Arc<Mutex<AppState>>,
+    app_ui: &Rc<RefCell<AppUI>>,
+    dir_path: &str,
+) {
+    remove_sections(app_state, app_ui, |path| {
+        Path::new(path).starts_with(dir_path)
+    });
+}
 
-            glib::MainContext::default().spawn_local(async move {
-                if index < overlays.len() {
-                    let overlay = overlays[index].clone();
-                    overlay.add_overlay(image_widget.widget());
-                    accordion_widget.borrow().flow_box.append(&overlay);
-                }
-            });
+// Drops the section of `dir_path` only
+fn remove_dir_section(
+    app_state: &Arc<Mutex<AppState>>,
+    app_ui: &Rc<RefCell<AppUI>>,
+    dir_path: &str,
+) {
+    remove_sections(app_state, app_ui, |path| path == dir_path);
+}
 
-            if index % 5 == 0 {
-                glib::timeout_future(Duration::from_millis(10)).await;
-            }
-        }
+fn remove_sections(
+    app_state: &Arc<Mutex<AppState>>,
+    app_ui: &Rc<RefCell<AppUI>>,
+    is_removed: impl Fn(&str) -> bool,
+) {
+    if let Ok(mut app_state) = app_state.lock() {
+        app_state.dir_entries.retain(|e| !is_removed(&e.dir_path));
     }
 
-    accordion_widget.borrow().progress_bar.set_visible(false);
+    let mut app_ui = app_ui.borrow_mut();
+    let AppUI {
+        top_vbox,
+        thumbnail_loader,
+        accordions,
+        ..
+    } = &mut *app_ui;
+
+    accordions.retain(|path, accordion_widget| {
+        if !is_removed(path) {
+            return true;
+        }
+        let accordion_widget = accordion_widget.borrow();
+        thumbnail_loader.cancel(&accordion_widget.progress);
+        top_vbox.remove(&accordion_widget.widget);
+        false
+    });
 }
 
 fn load_css() {
@@ -456,90 +1760,18 @@ fn load_css() {
     );
 }
 
+// Sections are always below the opened folder, so this works on the paths alone, even for folders that are gone
 fn get_relative_path(base_path: &str, path: &str) -> anyhow::Result<String> {
-    let base_path = Path::new(base_path).canonicalize()?;
-    let path = Path::new(path).canonicalize()?;
-    let relative_path = path.strip_prefix(&base_path)?;
-    let relative_path = relative_path.to_str().ok_or_else(|| {
-        anyhow::anyhow!(
-            "Failed to convert path to string: {:?}",
-            relative_path.to_str()
-        )
-    })?;
+    let relative_path = Path::new(path).strip_prefix(base_path)?;
 
-    if relative_path.is_empty() {
+    if relative_path.as_os_str().is_empty() {
         return Ok(String::from("."));
     }
 
-    Ok(relative_path.to_string())
-}
-
-fn open_with_xdg_open(image_path: String) -> anyhow::Result<()> {
-    let mut open_command = {
-        let app_config = APP_CONFIG
-            .read()
-            .map_err(|_| anyhow!("Failed to lock app config"))?;
-        app_config.open_command.clone()
-    };
-    let index = open_command.iter().position(|x| x == &"<path>".to_string());
-
-    let mut cmd = match index {
-        Some(index) => {
-            open_command[index] = image_path.clone();
-            let first_arg = open_command[0].clone();
-            let mut cmd = Command::new(&first_arg);
-            cmd.args(&open_command[1..]);
-            cmd
-        }
-        None => {
-            let app_config = AppConfig::default();
-            let first_arg = app_config.open_command[0].clone();
-            let mut cmd = Command::new(&first_arg);
-            cmd.args(&app_config.open_command[1..]);
-            cmd
-        }
-    };
-
-    cmd.stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .stdin(Stdio::null());
-
-    cmd.spawn()?;
-
-    Ok(())
+    Ok(relative_path.to_string_lossy().to_string())
 }
 
-fn natural_sort(a: &str, b: &str) -> anyhow::Result<Ordering> {
-    let re_all = Regex::new(r"(\d+)|(\D+)")?;
-    let re_num = Regex::new(r"^\d+$")?;
-
-    let a_parts: Vec<&str> = re_all.find_iter(a).map(|m| m.as_str()).collect();
-    let b_parts: Vec<&str> = re_all.find_iter(b).map(|m| m.as_str()).collect();
-
-    for i in 0..min(a_parts.len(), b_parts.len()) {
-        let a_part = a_parts[i];
-        let b_part = b_parts[i];
-
-        if a_part == b_part {
-            continue;
-        }
-
-        let order = if re_num.is_match(a_part) && re_num.is_match(b_part) {
-            let a_num = a_part.parse::<isize>().unwrap_or(0);
-            let b_num = b_part.parse::<isize>().unwrap_or(0);
-            a_num.cmp(&b_num)
-        } else if re_num.is_match(a_part) {
-            Ordering::Less
-        } else if re_num.is_match(b_part) {
-            Ordering::Greater
-        } else {
-            a_part.cmp(b_part)
-        };
-
-        if order != Ordering::Equal {
-            return Ok(order);
-        }
-    }
-
-    Ok(a_parts.len().cmp(&b_parts.len()))
+fn open_with_xdg_open(image_path: String, open_command: &[String]) -> anyhow::Result<()> {
+    let command = command_template::expand(open_command, &[image_path])?;
+    command_template::spawn(&command)
 }
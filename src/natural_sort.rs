@@ -0,0 +1,29 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+static PARTS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(\d+)|(\D+)").unwrap());
+
+// Numbers sort before text, and numbers compare by value rather than by their digits
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Part {
+    Number(u64),
+    Text(String),
+}
+
+// Computed once per path so sorting large folders does not re-split names on every comparison
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NaturalKey(Vec<Part>);
+
+impl NaturalKey {
+    pub fn new(s: &str) -> Self {
+        let parts = PARTS
+            .captures_iter(s)
+            .map(|caps| match caps.get(1) {
+                Some(number) => Part::Number(number.as_str().parse().unwrap_or(u64::MAX)),
+                None => Part::Text(caps[0].to_string()),
+            })
+            .collect();
+
+        Self(parts)
+    }
+}
@@ -0,0 +1,206 @@
+use crate::APP_CONFIG;
+use crate::app_config::AppConfig;
+use crate::command_template;
+use crate::folder_config::OpenCommand;
+use crate::open_with_xdg_open;
+use crate::problems::{ProblemKind, Problems};
+use anyhow::anyhow;
+use gtk4 as gtk;
+use gtk4::gio;
+use gtk4::glib;
+use gtk4::prelude::{
+    AppChooserExt, AppInfoExt, BoxExt, Cast, CastNone, CheckButtonExt, DialogExt,
+    GtkApplicationExt, GtkWindowExt, WidgetExt,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+// Open commands of `.gridx2.toml` files the user agreed to run this session. Any browsed folder, e.g. an
+// unpacked download, can bring one along, so each file and command is asked about once
+static APPROVED_COMMANDS: LazyLock<Mutex<HashSet<(PathBuf, Vec<String>)>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+
+// Lowercase, so "PNG" and "png" share one saved default
+pub fn extension(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+}
+
+// Installed applications for the MIME type of `path`, the system default first
+pub fn apps_for(path: &str) -> Vec<gio::AppInfo> {
+    let (content_type, _) = gio::content_type_guess(Some(path), &[]);
+
+    let mut apps: Vec<gio::AppInfo> = gio::AppInfo::default_for_type(&content_type, false)
+        .into_iter()
+        .collect();
+    for app in gio::AppInfo::all_for_type(&content_type) {
+        if !apps.iter().any(|known| known.id() == app.id()) {
+            apps.push(app);
+        }
+    }
+
+    apps.retain(|app| app.should_show());
+    apps
+}
+
+// Looks the desktop file up directly instead of listing every installed application
+fn find_app(id: &str) -> Option<gio::AppInfo> {
+    gio::DesktopAppInfo::new(id).map(|app| app.upcast())
+}
+
+// The application saved for the extension of `path`, if it is still installed
+fn saved_app(path: &str, problems: &Problems) -> Option<gio::AppInfo> {
+    let ext = extension(path)?;
+    let id = APP_CONFIG.read().ok()?.open_with.get(&ext)?.clone();
+
+    let app = find_app(&id);
+    if app.is_none() {
+        problems.notify(&format!(
+            "{id}, saved for .{ext} files, is not installed. Using the open command instead"
+        ));
+    }
+    app
+}
+
+pub fn launch(app: &gio::AppInfo, path: &str) -> anyhow::Result<()> {
+    app.launch(&[gio::File::for_path(path)], gio::AppLaunchContext::NONE)?;
+    Ok(())
+}
+
+// What a click on a thumbnail does: the saved application for its extension, otherwise the open command.
+// Lists `path` under Problems with a retry if that fails
+pub fn open_or_report(path: &str, open_command: &OpenCommand, problems: &Problems) {
+    let result = match saved_app(path, problems) {
+        Some(app) => launch(&app, path),
+        None if !is_approved(open_command) => {
+            glib::spawn_future_local(confirm_and_open(
+                path.to_string(),
+                open_command.clone(),
+                problems.clone(),
+            ));
+            return;
+        }
+        None => open_with_xdg_open(path.to_string(), &open_command.command),
+    };
+
+    match result {
+        Ok(()) => problems.resolve(ProblemKind::Open, path),
+        Err(e) => {
+            let path_inner = path.to_string();
+            let open_command = open_command.clone();
+            let problems_inner = problems.clone();
+            let retry = move || open_or_report(&path_inner, &open_command, &problems_inner);
+            problems.report(ProblemKind::Open, path, &format!("{e:#}"), Some(retry));
+        }
+    }
+}
+
+fn is_approved(open_command: &OpenCommand) -> bool {
+    let Some(source) = &open_command.source else {
+        return true;
+    };
+    APPROVED_COMMANDS
+        .lock()
+        .is_ok_and(|approved| approved.contains(&(source.clone(), open_command.command.clone())))
+}
+
+// Shows the command a folder brought along before it is run for the first time
+async fn confirm_and_open(path: String, open_command: OpenCommand, problems: Problems) {
+    let Some(source) = open_command.source.clone() else {
+        return;
+    };
+    let dialog = gtk::AlertDialog::builder()
+        .modal(true)
+        .message(format!(
+            "Run the open command of {}?",
+            source.display()
+        ))
+        .detail(format!(
+            "{}\n\nIt comes from the folder, not from your settings. Only run it if you trust that file.",
+            command_template::to_text(&open_command.command)
+        ))
+        .buttons(["Cancel", "Run"])
+        .cancel_button(0)
+        .default_button(0)
+        .build();
+    let parent = gio::Application::default()
+        .and_downcast::<gtk::Application>()
+        .and_then(|app| app.active_window());
+    if !matches!(dialog.choose_future(parent.as_ref()).await, Ok(1)) {
+        return;
+    }
+
+    if let Ok(mut approved) = APPROVED_COMMANDS.lock() {
+        approved.insert((source, open_command.command.clone()));
+    }
+    open_or_report(&path, &open_command, &problems);
+}
+
+// Same as `open_or_report` for an application picked from the menu
+pub fn launch_or_report(app: &gio::AppInfo, path: &str, problems: &Problems) {
+    match launch(app, path) {
+        Ok(()) => problems.resolve(ProblemKind::Open, path),
+        Err(e) => {
+            let app = app.clone();
+            let path_inner = path.to_string();
+            let problems_inner = problems.clone();
+            let retry = move || launch_or_report(&app, &path_inner, &problems_inner);
+            problems.report(ProblemKind::Open, path, &format!("{e:#}"), Some(retry));
+        }
+    }
+}
+
+// `None` removes the saved application, so the open command is used again.
+// Only this key is written back, command line overrides in APP_CONFIG stay out of the file
+pub fn save_default(ext: &str, app: Option<&gio::AppInfo>) -> anyhow::Result<()> {
+    let set = |open_with: &mut BTreeMap<String, String>| {
+        match app.and_then(|app| app.id()) {
+            Some(id) => open_with.insert(ext.to_string(), id.to_string()),
+            None => open_with.remove(ext),
+        };
+    };
+
+    let mut stored = AppConfig::reload()?.unwrap_or_default();
+    set(&mut stored.open_with);
+    set(&mut APP_CONFIG
+        .write()
+        .map_err(|_| anyhow!("Failed to lock app config"))?
+        .open_with);
+    stored.save()
+}
+
+// GtkAppChooserDialog is deprecated, but is still the only chooser offering every installed application
+#[allow(deprecated)]
+pub fn choose_app(parent: Option<&gtk::Window>, path: &str, problems: &Problems) {
+    let dialog = gtk::AppChooserDialog::new(
+        parent,
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        &gio::File::for_path(path),
+    );
+
+    let remember = gtk::CheckButton::with_label("Always use for this file type");
+    let ext = extension(path);
+    remember.set_visible(ext.is_some());
+    dialog.content_area().append(&remember);
+
+    let path = path.to_string();
+    let problems = problems.clone();
+    dialog.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Ok
+            && let Some(app) = dialog.app_info()
+        {
+            launch_or_report(&app, &path, &problems);
+            if remember.is_active()
+                && let Some(ext) = &ext
+                && let Err(e) = save_default(ext, Some(&app))
+            {
+                problems.notify(&format!("Failed to save the default application: {e}"));
+            }
+        }
+        dialog.destroy();
+    });
+
+    dialog.present();
+}
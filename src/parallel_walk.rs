@@ -0,0 +1,386 @@
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, mpsc};
+use std::thread;
+
+// Listing is mostly waiting on the disk, so a few more threads than cores still help
+const MAX_THREADS: usize = 8;
+
+// The walk has threads of its own. A task spawned from a rayon worker goes to that worker's local
+// queue, which is emptied before jobs injected from outside are looked at, so on the global pool a
+// large scan would hold back every thumbnail until it is done
+static POOL: LazyLock<ThreadPool> = LazyLock::new(|| {
+    let threads = thread::available_parallelism()
+        .map_or(MAX_THREADS, |threads| threads.get())
+        .min(MAX_THREADS);
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|index| format!("gridx2-walk-{index}"))
+        .build()
+        .expect("Failed to start the directory walker")
+});
+
+// Decides what the walker descends into and which files it reports. `State` is worked out once per
+// directory from the one of its parent, e.g. the settings cascading down the tree
+pub trait Visitor: Send + Sync + 'static {
+    type State: Send + Sync + 'static;
+
+    // Asked in the parent, before `dir` is listed at all
+    fn keep_dir(&self, parent: &Self::State, dir: &Path) -> bool;
+    fn enter(&self, parent: &Self::State, dir: &Path) -> Self::State;
+    fn keep_file(&self, state: &Self::State, path: &Path) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WalkOptions {
+    // Levels of subdirectories below the root, 0 lists the root only
+    pub max_depth: usize,
+    pub follow_links: bool,
+    pub same_file_system: bool,
+}
+
+// One directory and the files in it the visitor kept, sorted by name
+pub struct DirListing<S> {
+    pub path: PathBuf,
+    pub state: Arc<S>,
+    pub files: Vec<PathBuf>,
+    // Entries that could not be read, including the directory itself
+    pub errors: Vec<(PathBuf, io::Error)>,
+}
+
+struct Node<S> {
+    listing: DirListing<S>,
+    // In the order they are reported, each filled in by its own task
+    children: Vec<mpsc::Receiver<Node<S>>>,
+}
+
+struct Context<V> {
+    visitor: V,
+    options: WalkOptions,
+    root_device: Option<u64>,
+    cancel: Arc<AtomicBool>,
+}
+
+// Every directory is listed by its own task, but the listings come out in the same
+// order as a sequential walk: each directory before its subdirectories, siblings by name
+pub struct Walk<S> {
+    pending: Vec<mpsc::Receiver<Node<S>>>,
+}
+
+impl<S> Iterator for Walk<S> {
+    type Item = DirListing<S>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(rx) = self.pending.pop() {
+            // A closed channel means the walk was cancelled before the directory was listed
+            let Ok(node) = rx.recv() else {
+                continue;
+            };
+            self.pending.extend(node.children.into_iter().rev());
+            return Some(node.listing);
+        }
+        None
+    }
+}
+
+pub fn walk<V: Visitor>(
+    root: &Path,
+    root_state: V::State,
+    visitor: V,
+    options: WalkOptions,
+    cancel: Arc<AtomicBool>,
+) -> Walk<V::State> {
+    let (tx, rx) = mpsc::channel();
+    let walk = Walk { pending: vec![rx] };
+
+    let root_device = match options.same_file_system.then(|| device(root)) {
+        Some(Ok(root_device)) => Some(root_device),
+        Some(Err(e)) => {
+            let _ = tx.send(Node {
+                listing: DirListing {
+                    path: root.to_path_buf(),
+                    state: Arc::new(root_state),
+                    files: Vec::new(),
+                    errors: vec![(root.to_path_buf(), e)],
+                },
+                children: Vec::new(),
+            });
+            return walk;
+        }
+        None => None,
+    };
+    // Only needed to notice links back to a parent
+    let ancestors = if options.follow_links {
+        vec![fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf())]
+    } else {
+        Vec::new()
+    };
+
+    let context = Arc::new(Context {
+        visitor,
+        options,
+        root_device,
+        cancel,
+    });
+    let root = root.to_path_buf();
+    POOL.spawn(move || list_dir(&context, root, 0, Arc::new(root_state), ancestors, tx));
+
+    walk
+}
+
+fn list_dir<V: Visitor>(
+    context: &Arc<Context<V>>,
+    dir: PathBuf,
+    depth: usize,
+    state: Arc<V::State>,
+    ancestors: Vec<PathBuf>,
+    tx: mpsc::Sender<Node<V::State>>,
+) {
+    if context.cancel.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+    let mut errors = Vec::new();
+
+    match fs::read_dir(&dir) {
+        Ok(read_dir) => {
+            for entry in read_dir {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        errors.push((dir.clone(), e));
+                        continue;
+                    }
+                };
+                let path = entry.path();
+                match is_dir(&entry, context.options.follow_links) {
+                    Ok(true) => subdirs.push(path),
+                    Ok(false) => {
+                        if context.visitor.keep_file(&state, &path) {
+                            files.push(path);
+                        }
+                    }
+                    Err(e) => errors.push((path, e)),
+                }
+            }
+        }
+        Err(e) => errors.push((dir.clone(), e)),
+    }
+
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    subdirs.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+    let mut children = Vec::new();
+    if depth < context.options.max_depth {
+        for subdir in subdirs {
+            if !context.visitor.keep_dir(&state, &subdir) {
+                continue;
+            }
+            if let Some(root_device) = context.root_device {
+                match device(&subdir) {
+                    Ok(device) if device == root_device => {}
+                    Ok(_) => continue,
+                    Err(e) => {
+                        errors.push((subdir, e));
+                        continue;
+                    }
+                }
+            }
+
+            let mut ancestors = ancestors.clone();
+            if context.options.follow_links {
+                let canonical = match fs::canonicalize(&subdir) {
+                    Ok(canonical) => canonical,
+                    Err(e) => {
+                        errors.push((subdir, e));
+                        continue;
+                    }
+                };
+                // A link back to one of its own parents, which would otherwise be walked forever
+                if ancestors.contains(&canonical) {
+                    continue;
+                }
+                ancestors.push(canonical);
+            }
+
+            let (child_tx, child_rx) = mpsc::channel();
+            children.push(child_rx);
+
+            let context = context.clone();
+            let parent = state.clone();
+            POOL.spawn(move || {
+                let state = Arc::new(context.visitor.enter(&parent, &subdir));
+                list_dir(&context, subdir, depth + 1, state, ancestors, child_tx);
+            });
+        }
+    }
+
+    let _ = tx.send(Node {
+        listing: DirListing {
+            path: dir,
+            state,
+            files,
+            errors,
+        },
+        children,
+    });
+}
+
+fn is_dir(entry: &fs::DirEntry, follow_links: bool) -> io::Result<bool> {
+    let file_type = entry.file_type()?;
+    if file_type.is_symlink() && follow_links {
+        // A broken link is reported like a file, which only shows up if it is named like an image
+        return Ok(fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_dir()));
+    }
+    Ok(file_type.is_dir())
+}
+
+#[cfg(unix)]
+fn device(path: &Path) -> io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(fs::metadata(path)?.dev())
+}
+
+// Without device numbers every folder counts as being on the same file system
+#[cfg(not(unix))]
+fn device(_path: &Path) -> io::Result<u64> {
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct KeepAll;
+
+    impl Visitor for KeepAll {
+        type State = ();
+
+        fn keep_dir(&self, _parent: &(), _dir: &Path) -> bool {
+            true
+        }
+
+        fn enter(&self, _parent: &(), _dir: &Path) {}
+
+        fn keep_file(&self, _state: &(), _path: &Path) -> bool {
+            true
+        }
+    }
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("gridx2-walk-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn create(&self, relative: &str) {
+            let path = self.0.join(relative);
+            if relative.ends_with('/') {
+                fs::create_dir_all(path).unwrap();
+            } else {
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, b"").unwrap();
+            }
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn listings(root: &Path, options: WalkOptions) -> Vec<(String, Vec<String>)> {
+        let relative = |path: &Path| {
+            path.strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .to_string()
+        };
+        walk(root, (), KeepAll, options, Arc::new(AtomicBool::new(false)))
+            .map(|listing| {
+                assert!(listing.errors.is_empty(), "{:?}", listing.errors);
+                let files = listing.files.iter().map(|file| relative(file)).collect();
+                (relative(&listing.path), files)
+            })
+            .collect()
+    }
+
+    fn dirs(listings: &[(String, Vec<String>)]) -> Vec<&str> {
+        listings.iter().map(|(dir, _)| dir.as_str()).collect()
+    }
+
+    #[test]
+    fn reports_parents_first_and_siblings_by_name() {
+        let tree = TempDir::new("order");
+        for path in ["c/2", "a/z/1", "b/", "a/y", "a/x", "1"] {
+            tree.create(path);
+        }
+
+        let options = WalkOptions {
+            max_depth: 5,
+            ..WalkOptions::default()
+        };
+        let listings = listings(&tree.0, options);
+
+        assert_eq!(dirs(&listings), ["", "a", "a/z", "b", "c"]);
+        assert_eq!(listings[0].1, ["1"]);
+        assert_eq!(listings[1].1, ["a/x", "a/y"]);
+    }
+
+    #[test]
+    fn stops_at_max_depth() {
+        let tree = TempDir::new("depth");
+        tree.create("a/b/c/1");
+
+        let at_depth = |max_depth| {
+            let options = WalkOptions {
+                max_depth,
+                ..WalkOptions::default()
+            };
+            listings(&tree.0, options)
+        };
+
+        assert_eq!(dirs(&at_depth(0)), [""]);
+        assert_eq!(dirs(&at_depth(1)), ["", "a"]);
+        assert_eq!(dirs(&at_depth(3)), ["", "a", "a/b", "a/b/c"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_links_back_to_a_parent() {
+        let tree = TempDir::new("cycle");
+        tree.create("a/b/1");
+        tree.create("other/2");
+        std::os::unix::fs::symlink(&tree.0, tree.0.join("a/b/up")).unwrap();
+        std::os::unix::fs::symlink(tree.0.join("other"), tree.0.join("a/linked")).unwrap();
+
+        let options = WalkOptions {
+            max_depth: 10,
+            follow_links: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(
+            dirs(&listings(&tree.0, options)),
+            ["", "a", "a/b", "a/linked", "other"]
+        );
+
+        // Without following, links to folders are not walked
+        let options = WalkOptions {
+            max_depth: 10,
+            ..WalkOptions::default()
+        };
+        let listings = listings(&tree.0, options);
+        assert_eq!(dirs(&listings), ["", "a", "a/b", "other"]);
+    }
+}
@@ -0,0 +1,220 @@
+use gtk4 as gtk;
+use gtk4::prelude::{BoxExt, ButtonExt, ListBoxRowExt, WidgetExt};
+use gtk4::{glib, pango};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+// How long a notice stays up unless it is closed earlier
+const NOTICE_TIMEOUT: Duration = Duration::from_secs(6);
+const LIST_MAX_HEIGHT: i32 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProblemKind {
+    Decode,
+    Open,
+    Read,
+    Settings,
+    // The config file of the application, kept apart since a rescan does not read it again
+    Config,
+}
+
+impl ProblemKind {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Decode => "Could not decode",
+            Self::Open => "Could not open",
+            Self::Read => "Could not read",
+            Self::Settings => "Could not use",
+            Self::Config => "Could not load",
+        }
+    }
+}
+
+// Non-fatal failures. Files that failed stay listed in the collapsible panel until retried or resolved,
+// everything else is shown once as a notice above the status bar
+#[derive(Clone)]
+pub struct Problems {
+    pub widget: gtk::Box,
+    expander: gtk::Expander,
+    list: gtk::ListBox,
+    rows: Rc<RefCell<HashMap<(ProblemKind, String), gtk::ListBoxRow>>>,
+    notice: gtk::Revealer,
+    notice_label: gtk::Label,
+    notice_source: Rc<RefCell<Option<glib::SourceId>>>,
+}
+
+impl Problems {
+    pub fn new() -> Self {
+        let notice_label = gtk::Label::new(None);
+        notice_label.set_halign(gtk::Align::Start);
+        notice_label.set_hexpand(true);
+        notice_label.set_wrap(true);
+
+        let notice_close = gtk::Button::from_icon_name("window-close-symbolic");
+        notice_close.add_css_class("flat");
+
+        let notice_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        notice_box.add_css_class("notice");
+        notice_box.append(&notice_label);
+        notice_box.append(&notice_close);
+
+        let notice = gtk::Revealer::builder()
+            .transition_type(gtk::RevealerTransitionType::SlideUp)
+            .child(&notice_box)
+            .build();
+
+        let list = gtk::ListBox::new();
+        list.set_selection_mode(gtk::SelectionMode::None);
+
+        let scrolled_window = gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .vscrollbar_policy(gtk::PolicyType::Automatic)
+            .propagate_natural_height(true)
+            .max_content_height(LIST_MAX_HEIGHT)
+            .child(&list)
+            .build();
+
+        let expander = gtk::Expander::new(None);
+        expander.add_css_class("problems");
+        expander.set_child(Some(&scrolled_window));
+        expander.set_visible(false);
+
+        let widget = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        widget.append(&notice);
+        widget.append(&expander);
+
+        let problems = Self {
+            widget,
+            expander,
+            list,
+            rows: Rc::new(RefCell::new(HashMap::new())),
+            notice,
+            notice_label,
+            notice_source: Rc::new(RefCell::new(None)),
+        };
+
+        notice_close.connect_clicked(glib::clone!(
+            #[strong]
+            problems,
+            move |_| problems.hide_notice()
+        ));
+
+        problems
+    }
+
+    // Lists `path` in the panel, replacing an earlier entry for the same file and kind
+    pub fn report<F: Fn() + 'static>(
+        &self,
+        kind: ProblemKind,
+        path: &str,
+        reason: &str,
+        retry: Option<F>,
+    ) {
+        self.resolve(kind, path);
+
+        let title = gtk::Label::new(Some(&format!("{}: {path}", kind.label())));
+        title.set_halign(gtk::Align::Start);
+        title.set_ellipsize(pango::EllipsizeMode::Middle);
+        title.set_tooltip_text(Some(path));
+
+        let reason_label = gtk::Label::new(Some(reason));
+        reason_label.set_halign(gtk::Align::Start);
+        reason_label.set_wrap(true);
+        reason_label.add_css_class("dim-label");
+
+        let text_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+        text_box.set_hexpand(true);
+        text_box.append(&title);
+        text_box.append(&reason_label);
+
+        let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        row_box.append(&text_box);
+
+        let key = (kind, path.to_string());
+        if let Some(retry) = retry {
+            let retry_button = gtk::Button::with_label("Retry");
+            retry_button.set_valign(gtk::Align::Center);
+            let problems = self.clone();
+            let key = key.clone();
+            retry_button.connect_clicked(move |_| {
+                problems.resolve(key.0, &key.1);
+                retry();
+            });
+            row_box.append(&retry_button);
+        }
+
+        let row = gtk::ListBoxRow::new();
+        row.set_activatable(false);
+        row.set_child(Some(&row_box));
+        self.list.append(&row);
+
+        self.rows.borrow_mut().insert(key, row);
+        self.update_title();
+    }
+
+    // The file loaded or opened after all
+    pub fn resolve(&self, kind: ProblemKind, path: &str) {
+        let row = self.rows.borrow_mut().remove(&(kind, path.to_string()));
+        if let Some(row) = row {
+            self.list.remove(&row);
+            self.update_title();
+        }
+    }
+
+    // A different folder was opened, so nothing listed about files applies anymore
+    pub fn clear(&self) {
+        self.remove_where(|kind| kind != ProblemKind::Config);
+    }
+
+    // Drops every entry of one kind, before it is checked again
+    pub fn clear_kind(&self, kind: ProblemKind) {
+        self.remove_where(|other| other == kind);
+    }
+
+    fn remove_where(&self, is_removed: impl Fn(ProblemKind) -> bool) {
+        let rows: Vec<gtk::ListBoxRow> = {
+            let mut rows = self.rows.borrow_mut();
+            let keys: Vec<_> = rows
+                .keys()
+                .filter(|key| is_removed(key.0))
+                .cloned()
+                .collect();
+            keys.iter().filter_map(|key| rows.remove(key)).collect()
+        };
+        for row in rows {
+            self.list.remove(&row);
+        }
+        self.update_title();
+    }
+
+    pub fn notify(&self, text: &str) {
+        self.notice_label.set_text(text);
+        self.notice.set_reveal_child(true);
+
+        if let Some(source) = self.notice_source.borrow_mut().take() {
+            source.remove();
+        }
+        let problems = self.clone();
+        let source = glib::timeout_add_local_once(NOTICE_TIMEOUT, move || {
+            problems.notice_source.replace(None);
+            problems.notice.set_reveal_child(false);
+        });
+        self.notice_source.replace(Some(source));
+    }
+
+    fn hide_notice(&self) {
+        if let Some(source) = self.notice_source.borrow_mut().take() {
+            source.remove();
+        }
+        self.notice.set_reveal_child(false);
+    }
+
+    fn update_title(&self) {
+        let count = self.rows.borrow().len();
+        self.expander
+            .set_label(Some(&format!("Problems ({count})")));
+        self.expander.set_visible(count > 0);
+    }
+}
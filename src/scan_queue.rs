@@ -0,0 +1,56 @@
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+// Why the main loop stopped taking events off the scan channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drained {
+    // Nothing new for now, the scan is still running
+    Empty,
+    // More is waiting, but the window has to draw first
+    OutOfTime,
+    // The scan is done and every event was handled
+    Finished,
+}
+
+// Handles events until the channel is empty or `budget` is used up, so a fast scan neither falls
+// behind nor freezes the window
+pub fn drain<T>(rx: &mpsc::Receiver<T>, budget: Duration, mut handle: impl FnMut(T)) -> Drained {
+    let start = Instant::now();
+    loop {
+        match rx.try_recv() {
+            Ok(event) => handle(event),
+            Err(mpsc::TryRecvError::Empty) => return Drained::Empty,
+            Err(mpsc::TryRecvError::Disconnected) => return Drained::Finished,
+        }
+        if start.elapsed() >= budget {
+            return Drained::OutOfTime;
+        }
+    }
+}
+
+// Lets through at most one event per `interval`, for progress the window shows once per tick anyway
+pub struct Throttle {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    pub fn ready(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .last
+            .is_some_and(|last| now.duration_since(last) < self.interval)
+        {
+            return false;
+        }
+        self.last = Some(now);
+        true
+    }
+}
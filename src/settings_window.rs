@@ -1,14 +1,275 @@
-use crate::APP_CONFIG;
+use crate::app_config::{self, AppConfig};
+use crate::command_template;
+use crate::custom_action::CustomAction;
+use crate::image_format;
+use crate::texture_cache::parse_size;
+use anyhow::anyhow;
 use gtk4 as gtk;
-use gtk4::prelude::{BoxExt, ButtonExt, EditableExt, GtkWindowExt, WidgetExt};
-use gtk4::{glib, Adjustment, ApplicationWindow, SpinButton};
+use gtk4::prelude::{
+    AdjustmentExt, BoxExt, ButtonExt, CastNone, CheckButtonExt, EditableExt, GtkWindowExt,
+    WidgetExt,
+};
+use gtk4::{Adjustment, ApplicationWindow, DropDown, SpinButton, glib, pango};
+use ignore::gitignore::GitignoreBuilder;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+// Order of the entries in the theme drop-down
+const THEME_SYSTEM: u32 = 0;
+const THEME_DARK: u32 = 1;
+const THEME_LIGHT: u32 = 2;
 
 pub struct SettingsWindow {
     window: ApplicationWindow,
 }
 
+// One widget per field of `AppConfig` the user can change
+#[derive(Clone)]
+struct SettingsForm {
+    max_depth_spin: SpinButton,
+    thumbnail_spin: SpinButton,
+    command_entry: gtk::Entry,
+    theme_dropdown: DropDown,
+    cache_budget_entry: gtk::Entry,
+    extensions_entry: gtk::Entry,
+    sniff_check: gtk::CheckButton,
+    ignore_entry: gtk::Entry,
+    hidden_check: gtk::CheckButton,
+    symlinks_check: gtk::CheckButton,
+    same_fs_check: gtk::CheckButton,
+    // Only added in the file, so the window lists them and lets entries be removed
+    open_with_list: gtk::ListBox,
+    open_with: Rc<RefCell<BTreeMap<String, String>>>,
+    actions_list: gtk::ListBox,
+    // Removed actions become None, so the rows keep their index
+    actions: Rc<RefCell<Vec<Option<CustomAction>>>>,
+}
+
+impl SettingsForm {
+    fn fill(&self, config: &AppConfig) {
+        self.max_depth_spin.set_value(config.max_depth as f64);
+        self.thumbnail_spin.set_value(config.thumbnail_size as f64);
+        self.command_entry
+            .set_text(&command_template::to_text(&config.open_command));
+        self.theme_dropdown.set_selected(match config.dark_mode {
+            None => THEME_SYSTEM,
+            Some(true) => THEME_DARK,
+            Some(false) => THEME_LIGHT,
+        });
+        self.cache_budget_entry.set_text(&config.cache_budget);
+        self.extensions_entry.set_text(
+            &config
+                .image_extensions
+                .as_deref()
+                .unwrap_or_default()
+                .join(" "),
+        );
+        self.sniff_check.set_active(config.sniff_content);
+        self.ignore_entry
+            .set_text(&config.ignore_patterns.join(", "));
+        self.hidden_check.set_active(config.show_hidden);
+        self.symlinks_check.set_active(config.follow_symlinks);
+        self.same_fs_check.set_active(config.same_file_system);
+
+        self.open_with.replace(config.open_with.clone());
+        self.fill_open_with();
+        self.actions
+            .replace(config.actions.iter().cloned().map(Some).collect());
+        self.fill_actions();
+    }
+
+    fn fill_open_with(&self) {
+        self.open_with_list.remove_all();
+        for (ext, app_id) in self.open_with.borrow().iter() {
+            let open_with = self.open_with.clone();
+            let ext_inner = ext.clone();
+            let row = removable_row(&format!(".{ext}: {app_id}"), app_id, move || {
+                open_with.borrow_mut().remove(&ext_inner);
+            });
+            self.open_with_list.append(&row);
+        }
+    }
+
+    fn fill_actions(&self) {
+        self.actions_list.remove_all();
+        for (index, action) in self.actions.borrow().iter().enumerate() {
+            let Some(action) = action else {
+                continue;
+            };
+            let label = match &action.keybinding {
+                Some(keybinding) => format!("{} ({keybinding})", action.name),
+                None => action.name.clone(),
+            };
+            let actions = self.actions.clone();
+            let row = removable_row(
+                &label,
+                &command_template::to_text(&action.command),
+                move || {
+                    actions.borrow_mut()[index] = None;
+                },
+            );
+            self.actions_list.append(&row);
+        }
+    }
+
+    // Returns `base` with the values of the form, or why they can not be saved. Fields left as `stored`
+    // filled them in keep the value of `base`
+    fn read(&self, stored: &AppConfig, base: &AppConfig) -> anyhow::Result<AppConfig> {
+        let open_command = command_template::parse(&self.command_entry.text())?;
+
+        let cache_budget = self.cache_budget_entry.text().trim().to_string();
+        if parse_size(&cache_budget)? == 0 {
+            return Err(anyhow!("The cache budget must be larger than 0"));
+        }
+
+        // Empty means every supported format
+        let extensions: Vec<String> = self
+            .extensions_entry
+            .text()
+            .split([' ', ','])
+            .filter(|ext| !ext.is_empty())
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect();
+        if let Some(ext) = extensions
+            .iter()
+            .find(|ext| !image_format::is_readable(ext))
+        {
+            return Err(anyhow!("No decoder for .{ext} images is available"));
+        }
+
+        let ignore_patterns: Vec<String> = self
+            .ignore_entry
+            .text()
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(String::from)
+            .collect();
+        let mut ignore_builder = GitignoreBuilder::new("/");
+        for pattern in &ignore_patterns {
+            ignore_builder
+                .add_line(None, pattern)
+                .map_err(|e| anyhow!("Invalid ignore pattern {pattern}: {e}"))?;
+        }
+
+        let open_with = self.open_with.borrow().clone();
+        let actions: Vec<CustomAction> = self.actions.borrow().iter().flatten().cloned().collect();
+
+        Ok(AppConfig {
+            max_depth: spin_value(&self.max_depth_spin, stored.max_depth, base.max_depth),
+            thumbnail_size: spin_value(
+                &self.thumbnail_spin,
+                stored.thumbnail_size,
+                base.thumbnail_size,
+            ),
+            open_command,
+            dark_mode: match self.theme_dropdown.selected() {
+                THEME_DARK => Some(true),
+                THEME_LIGHT => Some(false),
+                _ => None,
+            },
+            cache_budget,
+            image_extensions: (!extensions.is_empty()).then_some(extensions),
+            sniff_content: self.sniff_check.is_active(),
+            ignore_patterns,
+            show_hidden: self.hidden_check.is_active(),
+            follow_symlinks: self.symlinks_check.is_active(),
+            same_file_system: self.same_fs_check.is_active(),
+            open_with: if open_with == stored.open_with {
+                base.open_with.clone()
+            } else {
+                open_with
+            },
+            actions: if actions == stored.actions {
+                base.actions.clone()
+            } else {
+                actions
+            },
+            ..base.clone()
+        })
+    }
+}
+
+// A spin button clamps what it shows, so a value outside its range is only replaced once it is changed
+fn spin_value(spin: &SpinButton, stored: u32, base: u32) -> u32 {
+    let adjustment = spin.adjustment();
+    let shown = (stored as f64).clamp(adjustment.lower(), adjustment.upper());
+    if spin.value() == shown {
+        base
+    } else {
+        spin.value() as u32
+    }
+}
+
+fn range_adjustment(range: RangeInclusive<u32>, step: f64, page: f64) -> Adjustment {
+    Adjustment::new(
+        *range.start() as f64,
+        *range.start() as f64,
+        *range.end() as f64,
+        step,
+        page,
+        0.0,
+    )
+}
+
+// One entry of a list edited in the file, with a button that takes it out of the form
+fn removable_row<F: Fn() + 'static>(label: &str, tooltip: &str, on_remove: F) -> gtk::ListBoxRow {
+    let label = gtk::Label::new(Some(label));
+    label.set_halign(gtk::Align::Start);
+    label.set_hexpand(true);
+    label.set_ellipsize(pango::EllipsizeMode::End);
+    label.set_tooltip_text(Some(tooltip));
+
+    let remove_button = gtk::Button::from_icon_name("list-remove-symbolic");
+    remove_button.add_css_class("flat");
+    remove_button.set_tooltip_text(Some("Remove"));
+
+    let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    row_box.append(&label);
+    row_box.append(&remove_button);
+
+    let row = gtk::ListBoxRow::new();
+    row.set_activatable(false);
+    row.set_child(Some(&row_box));
+
+    remove_button.connect_clicked(glib::clone!(
+        #[weak]
+        row,
+        move |_| {
+            on_remove();
+            if let Some(list) = row.parent().and_downcast::<gtk::ListBox>() {
+                list.remove(&row);
+            }
+        }
+    ));
+
+    row
+}
+
+fn list_section(vbox: &gtk::Box, title: &str, placeholder: &str) -> gtk::ListBox {
+    let label = gtk::Label::new(Some(title));
+    label.set_halign(gtk::Align::Start);
+    vbox.append(&label);
+
+    let placeholder = gtk::Label::new(Some(placeholder));
+    placeholder.add_css_class("dim-label");
+
+    let list = gtk::ListBox::new();
+    list.set_selection_mode(gtk::SelectionMode::None);
+    list.set_placeholder(Some(&placeholder));
+    vbox.append(&list);
+    list
+}
+
 impl SettingsWindow {
-    pub fn new(parent: &ApplicationWindow) -> anyhow::Result<Self> {
+    // The form shows the config file as written, without values given on the command line.
+    // `on_save` gets the file before and after saving, so the caller can apply what changed
+    pub fn new<F: Fn(&AppConfig, &AppConfig) + 'static>(
+        parent: &ApplicationWindow,
+        on_save: F,
+    ) -> anyhow::Result<Self> {
         let window = ApplicationWindow::builder()
             .title("Settings")
             .default_width(300)
@@ -27,7 +288,7 @@ impl SettingsWindow {
         let max_depth_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
         let max_depth_label = gtk::Label::new(Some("Max depth:"));
         let max_depth_spin = SpinButton::new(
-            Some(&Adjustment::new(0.0, 1.0, 10.0, 1.0, 5.0, 0.0)),
+            Some(&range_adjustment(app_config::MAX_DEPTHS, 1.0, 5.0)),
             1.0,
             0,
         );
@@ -36,10 +297,17 @@ impl SettingsWindow {
         max_depth_box.append(&max_depth_spin);
         vbox.append(&max_depth_box);
 
+        let symlinks_check = gtk::CheckButton::with_label("Follow symbolic links");
+        vbox.append(&symlinks_check);
+
+        let same_fs_check = gtk::CheckButton::with_label("Stay on the same file system");
+        same_fs_check.set_tooltip_text(Some("Skips mounted drives and network shares"));
+        vbox.append(&same_fs_check);
+
         let thumbnail_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
         let thumbnail_label = gtk::Label::new(Some("Thumbnail size:"));
         let thumbnail_spin = SpinButton::new(
-            Some(&Adjustment::new(0.0, 50.0, 500.0, 10.0, 50.0, 0.0)),
+            Some(&range_adjustment(app_config::THUMBNAIL_SIZES, 10.0, 50.0)),
             1.0,
             0,
         );
@@ -58,27 +326,115 @@ impl SettingsWindow {
         command_box.append(&command_entry);
         vbox.append(&command_box);
 
-        let hint_label = gtk::Label::new(Some("Hint: the actual path is assigned to <path>"));
+        let hint_label = gtk::Label::new(Some(&format!(
+            "Hint: quote arguments with spaces. {} are replaced for the opened file",
+            command_template::PLACEHOLDERS.join(" ")
+        )));
+        hint_label.set_wrap(true);
         hint_label.set_halign(gtk::Align::Start);
         vbox.append(&hint_label);
 
+        let theme_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        let theme_label = gtk::Label::new(Some("Theme:"));
+        let theme_dropdown = DropDown::from_strings(&["Follow system", "Dark", "Light"]);
+
+        theme_box.append(&theme_label);
+        theme_box.append(&theme_dropdown);
+        vbox.append(&theme_box);
+
+        let cache_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        let cache_label = gtk::Label::new(Some("Thumbnail memory:"));
+        let cache_budget_entry = gtk::Entry::new();
+        cache_budget_entry.set_placeholder_text(Some("e.g. 512 MiB"));
+
+        cache_box.append(&cache_label);
+        cache_box.append(&cache_budget_entry);
+        vbox.append(&cache_box);
+
+        let extensions_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        let extensions_label = gtk::Label::new(Some("Image extensions:"));
+        let extensions_entry = gtk::Entry::new();
+        extensions_entry.set_placeholder_text(Some("All supported"));
+        extensions_entry.set_tooltip_text(Some(&format!(
+            "Supported: {}",
+            image_format::supported_extensions().join(" ")
+        )));
+
+        extensions_box.append(&extensions_label);
+        extensions_box.append(&extensions_entry);
+        vbox.append(&extensions_box);
+
+        let sniff_check = gtk::CheckButton::with_label("Detect images by content");
+        sniff_check.set_tooltip_text(Some(
+            "Also shows files with an unknown or missing extension that are images",
+        ));
+        vbox.append(&sniff_check);
+
+        let ignore_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        let ignore_label = gtk::Label::new(Some("Ignore:"));
+        let ignore_entry = gtk::Entry::new();
+        ignore_entry.set_placeholder_text(Some("e.g. node_modules/, *.tmp"));
+        ignore_entry.set_tooltip_text(Some(
+            "Comma separated .gitignore patterns, relative to the opened folder",
+        ));
+
+        ignore_box.append(&ignore_label);
+        ignore_box.append(&ignore_entry);
+        vbox.append(&ignore_box);
+
+        let hidden_check = gtk::CheckButton::with_label("Show hidden files and folders");
+        vbox.append(&hidden_check);
+
+        let open_with_list = list_section(
+            &vbox,
+            "Default applications:",
+            "Saved with \"Always Open .ext With\" on a thumbnail",
+        );
+        let actions_list = list_section(&vbox, "Actions:", "Added as [[actions]] in config.toml");
+
+        let error_label = gtk::Label::new(None);
+        error_label.set_halign(gtk::Align::Start);
+        error_label.add_css_class("error");
+        error_label.set_visible(false);
+        vbox.append(&error_label);
+
         let button_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        let button_defaults = gtk::Button::with_label("Restore defaults");
         let button_save = gtk::Button::with_label("Save");
         let button_cancel = gtk::Button::with_label("Cancel");
 
+        button_box.append(&button_defaults);
         button_box.append(&button_save);
         button_box.append(&button_cancel);
         vbox.append(&button_box);
 
-        let current_config = {
-            let config = APP_CONFIG
-                .read()
-                .map_err(|_| anyhow::anyhow!("Failed to lock config"))?;
-            config.clone()
+        let form = SettingsForm {
+            max_depth_spin,
+            thumbnail_spin,
+            command_entry,
+            theme_dropdown,
+            cache_budget_entry,
+            extensions_entry,
+            sniff_check,
+            ignore_entry,
+            hidden_check,
+            symlinks_check,
+            same_fs_check,
+            open_with_list,
+            open_with: Rc::new(RefCell::new(BTreeMap::new())),
+            actions_list,
+            actions: Rc::new(RefCell::new(Vec::new())),
         };
-        max_depth_spin.set_value(current_config.max_depth as f64);
-        thumbnail_spin.set_value(current_config.thumbnail_size as f64);
-        command_entry.set_text(&current_config.open_command.join(" "));
+
+        let stored = AppConfig::reload()?.unwrap_or_default();
+        form.fill(&stored);
+
+        // Only fills in the form, nothing changes until Save is pressed
+        button_defaults.connect_clicked(glib::clone!(
+            #[strong]
+            form,
+            move |_| form.fill(&AppConfig::default())
+        ));
 
         button_cancel.connect_clicked(glib::clone!(
             #[weak]
@@ -90,27 +446,27 @@ impl SettingsWindow {
             #[weak]
             window,
             #[weak]
-            max_depth_spin,
-            #[weak]
-            thumbnail_spin,
-            #[weak]
-            command_entry,
+            error_label,
             move |_| {
-                let mut config = match APP_CONFIG.write() {
+                // Keys edited in the file while the window was open are kept
+                let config = AppConfig::reload()
+                    .map(|current| current.unwrap_or_else(|| stored.clone()))
+                    .and_then(|current| form.read(&stored, &current));
+                let config = match config {
                     Ok(config) => config,
-                    Err(_) => return,
+                    Err(e) => {
+                        error_label.set_text(&e.to_string());
+                        error_label.set_visible(true);
+                        return;
+                    }
                 };
 
-                config.max_depth = max_depth_spin.value() as u32;
-                config.thumbnail_size = thumbnail_spin.value() as u32;
-                config.open_command = command_entry
-                    .text()
-                    .split_whitespace()
-                    .map(|s| s.to_string())
-                    .collect();
+                on_save(&stored, &config);
 
                 if let Err(e) = config.save() {
-                    eprintln!("Failed to save config: {e}");
+                    error_label.set_text(&format!("Applied, but could not be saved: {e}"));
+                    error_label.set_visible(true);
+                    return;
                 }
 
                 window.close();
@@ -0,0 +1,250 @@
+use gtk4::gdk::Texture;
+use gtk4::prelude::TextureExt;
+use lru::LruCache;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+// A thumbnail is only reused while the file it was made from is unchanged and the requested size matches
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub path: String,
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+    pub thumbnail_size: u32,
+}
+
+impl CacheKey {
+    pub fn new(path: &str, thumbnail_size: u32) -> anyhow::Result<Self> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            thumbnail_size,
+        })
+    }
+}
+
+// Least recently used textures are evicted once their decoded size exceeds the budget
+pub struct TextureCache {
+    entries: LruCache<CacheKey, Arc<Texture>>,
+    // The only key kept per path, so a changed file or size replaces its old thumbnail
+    keys: HashMap<String, CacheKey>,
+    used_bytes: usize,
+    budget_bytes: usize,
+}
+
+impl TextureCache {
+    pub fn new(budget_bytes: usize) -> Self {
+        Self {
+            entries: LruCache::unbounded(),
+            keys: HashMap::new(),
+            used_bytes: 0,
+            budget_bytes,
+        }
+    }
+
+    pub fn get(&mut self, key: &CacheKey) -> Option<&Arc<Texture>> {
+        if self
+            .keys
+            .get(&key.path)
+            .is_some_and(|current| current != key)
+        {
+            self.remove(&key.path);
+            return None;
+        }
+        self.entries.get(key)
+    }
+
+    // Does not touch the file, so it may still report a thumbnail that `get` would find stale
+    pub fn contains(&self, path: &str, thumbnail_size: u32) -> bool {
+        self.keys
+            .get(path)
+            .is_some_and(|key| key.thumbnail_size == thumbnail_size)
+    }
+
+    pub fn put(&mut self, key: CacheKey, texture: Arc<Texture>) {
+        self.remove(&key.path);
+
+        self.used_bytes += texture_bytes(&texture);
+        self.keys.insert(key.path.clone(), key.clone());
+        self.entries.put(key, texture);
+        self.evict();
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        if let Some(key) = self.keys.remove(path)
+            && let Some(texture) = self.entries.pop(&key)
+        {
+            self.used_bytes -= texture_bytes(&texture);
+        }
+    }
+
+    pub fn set_budget(&mut self, budget_bytes: usize) {
+        self.budget_bytes = budget_bytes;
+        self.evict();
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    pub fn budget_bytes(&self) -> usize {
+        self.budget_bytes
+    }
+
+    fn evict(&mut self) {
+        while self.used_bytes > self.budget_bytes {
+            match self.entries.pop_lru() {
+                Some((key, texture)) => {
+                    self.keys.remove(&key.path);
+                    self.used_bytes -= texture_bytes(&texture);
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+// Thumbnails are uploaded as 8-bit RGBA
+fn texture_bytes(texture: &Texture) -> usize {
+    texture.width() as usize * texture.height() as usize * 4
+}
+
+// Accepts sizes like "512 MiB", "1.5GiB", "800 MB" or a plain number of bytes
+pub fn parse_size(size: &str) -> anyhow::Result<usize> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size: {size:?}"))?;
+    let multiplier: f64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "g" | "gb" => 1e9,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        unit => return Err(anyhow::anyhow!("Unknown size unit: {unit:?}")),
+    };
+
+    Ok((number * multiplier) as usize)
+}
+
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gtk4::gdk::{MemoryFormat, MemoryTexture};
+    use gtk4::glib;
+    use gtk4::prelude::Cast;
+
+    // 400 bytes per texture
+    const SIDE: usize = 10;
+    const TEXTURE_BYTES: usize = SIDE * SIDE * 4;
+
+    fn key(path: &str, thumbnail_size: u32) -> CacheKey {
+        CacheKey {
+            path: path.to_string(),
+            modified: None,
+            len: 0,
+            thumbnail_size,
+        }
+    }
+
+    fn texture(side: usize) -> Arc<Texture> {
+        let pixels = glib::Bytes::from_owned(vec![0u8; side * side * 4]);
+        let texture = MemoryTexture::new(
+            side as i32,
+            side as i32,
+            MemoryFormat::R8g8b8a8,
+            &pixels,
+            side * 4,
+        );
+        Arc::new(texture.upcast())
+    }
+
+    #[test]
+    fn evicts_least_recently_used_first() {
+        let mut cache = TextureCache::new(2 * TEXTURE_BYTES);
+        cache.put(key("a", 100), texture(SIDE));
+        cache.put(key("b", 100), texture(SIDE));
+        assert!(cache.get(&key("a", 100)).is_some());
+
+        cache.put(key("c", 100), texture(SIDE));
+
+        assert!(cache.contains("a", 100));
+        assert!(!cache.contains("b", 100));
+        assert!(cache.contains("c", 100));
+        assert_eq!(cache.used_bytes(), 2 * TEXTURE_BYTES);
+    }
+
+    #[test]
+    fn put_replaces_the_entry_of_the_same_path() {
+        let mut cache = TextureCache::new(usize::MAX);
+        cache.put(key("a", 100), texture(SIDE));
+        cache.put(key("a", 200), texture(2 * SIDE));
+
+        assert!(!cache.contains("a", 100));
+        assert!(cache.contains("a", 200));
+        assert!(cache.get(&key("a", 100)).is_none());
+        assert_eq!(cache.used_bytes(), 4 * TEXTURE_BYTES);
+    }
+
+    #[test]
+    fn lower_budget_evicts_entries() {
+        let mut cache = TextureCache::new(usize::MAX);
+        for path in ["a", "b", "c"] {
+            cache.put(key(path, 100), texture(SIDE));
+        }
+
+        cache.set_budget(TEXTURE_BYTES);
+
+        assert!(!cache.contains("a", 100));
+        assert!(!cache.contains("b", 100));
+        assert!(cache.contains("c", 100));
+        assert_eq!(cache.used_bytes(), TEXTURE_BYTES);
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512 MiB").unwrap(), 512 * 1024 * 1024);
+        assert_eq!(parse_size("1.5GiB").unwrap(), 1536 * 1024 * 1024);
+        assert_eq!(parse_size("800 MB").unwrap(), 800_000_000);
+        assert_eq!(parse_size(" 4096 ").unwrap(), 4096);
+        assert!(parse_size("512 MiBs").is_err());
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("").is_err());
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(512 * 1024 * 1024), "512.0 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
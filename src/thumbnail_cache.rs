@@ -0,0 +1,190 @@
+// Persistent thumbnails following the freedesktop thumbnail specification:
+// https://specifications.freedesktop.org/thumbnail-spec/latest/
+use anyhow::anyhow;
+use gtk4::glib;
+use image::DynamicImage;
+use std::fs;
+use std::io::{BufReader, BufWriter};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const KEY_URI: &str = "Thumb::URI";
+const KEY_MTIME: &str = "Thumb::MTime";
+const KEY_SIZE: &str = "Thumb::Size";
+const KEY_SOFTWARE: &str = "Software";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailFlavor {
+    Normal,
+    Large,
+    XLarge,
+}
+
+impl ThumbnailFlavor {
+    pub fn for_size(thumbnail_size: u32) -> Option<Self> {
+        match thumbnail_size {
+            0..=128 => Some(Self::Normal),
+            129..=256 => Some(Self::Large),
+            257..=512 => Some(Self::XLarge),
+            _ => None,
+        }
+    }
+
+    pub fn pixels(&self) -> u32 {
+        match self {
+            Self::Normal => 128,
+            Self::Large => 256,
+            Self::XLarge => 512,
+        }
+    }
+
+    fn dir_name(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Large => "large",
+            Self::XLarge => "x-large",
+        }
+    }
+}
+
+pub struct SourceInfo {
+    uri: String,
+    mtime: u64,
+    size: u64,
+}
+
+impl SourceInfo {
+    pub fn new<T: AsRef<Path>>(image_path: T) -> anyhow::Result<Self> {
+        let absolute = std::path::absolute(image_path.as_ref())?;
+        let metadata = fs::metadata(&absolute)?;
+        let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+        let uri = glib::filename_to_uri(&absolute, None)?.to_string();
+
+        Ok(Self {
+            uri,
+            mtime,
+            size: metadata.len(),
+        })
+    }
+
+    fn thumbnail_path(&self, flavor: ThumbnailFlavor) -> anyhow::Result<PathBuf> {
+        let name = format!("{:x}.png", md5::compute(self.uri.as_bytes()));
+        Ok(thumbnails_dir()?.join(flavor.dir_name()).join(name))
+    }
+}
+
+// Returns the stored thumbnail if it exists and still describes the source file
+pub fn load(source: &SourceInfo, flavor: ThumbnailFlavor) -> Option<DynamicImage> {
+    let path = source.thumbnail_path(flavor).ok()?;
+    let file = fs::File::open(&path).ok()?;
+
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    // Thumbnails written by other programs may be paletted or 16-bit
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let text = &reader.info().uncompressed_latin1_text;
+    let find = |key: &str| {
+        text.iter()
+            .find(|chunk| chunk.keyword == key)
+            .map(|chunk| chunk.text.as_str())
+    };
+
+    if find(KEY_URI) != Some(source.uri.as_str()) {
+        return None;
+    }
+    if find(KEY_MTIME).and_then(|mtime| mtime.parse::<u64>().ok()) != Some(source.mtime) {
+        return None;
+    }
+
+    // The pixels are read from the same file that was checked above
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut pixels).ok()?;
+    pixels.truncate(frame.buffer_size());
+
+    let (width, height) = (frame.width, frame.height);
+    match frame.color_type {
+        png::ColorType::Rgba => image::RgbaImage::from_raw(width, height, pixels).map(Into::into),
+        png::ColorType::Rgb => image::RgbImage::from_raw(width, height, pixels).map(Into::into),
+        png::ColorType::GrayscaleAlpha => {
+            image::GrayAlphaImage::from_raw(width, height, pixels).map(Into::into)
+        }
+        png::ColorType::Grayscale => {
+            image::GrayImage::from_raw(width, height, pixels).map(Into::into)
+        }
+        png::ColorType::Indexed => None,
+    }
+}
+
+pub fn save(
+    source: &SourceInfo,
+    flavor: ThumbnailFlavor,
+    img: &DynamicImage,
+) -> anyhow::Result<()> {
+    let path = source.thumbnail_path(flavor)?;
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid thumbnail path: {path:?}"))?;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+
+    // Write next to the final location first so readers never see a partial file
+    let tmp_path = dir.join(format!(
+        ".gridx2-{}-{}",
+        std::process::id(),
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let file = fs::File::create(&tmp_path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+
+    let result = (|| -> anyhow::Result<()> {
+        let mut encoder = png::Encoder::new(BufWriter::new(&file), width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.add_text_chunk(KEY_URI.into(), source.uri.clone())?;
+        encoder.add_text_chunk(KEY_MTIME.into(), source.mtime.to_string())?;
+        encoder.add_text_chunk(KEY_SIZE.into(), source.size.to_string())?;
+        encoder.add_text_chunk(KEY_SOFTWARE.into(), "gridx2".into())?;
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(rgba.as_raw())?;
+        writer.finish()?;
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => {
+            fs::rename(&tmp_path, &path)?;
+            Ok(())
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+// Thumbnails are always scaled to fit the flavor; images smaller than that are stored as-is
+pub fn make_thumbnail(img: &DynamicImage, flavor: ThumbnailFlavor) -> DynamicImage {
+    let max = flavor.pixels();
+    if img.width() <= max && img.height() <= max {
+        return img.clone();
+    }
+    img.thumbnail(max, max)
+}
+
+fn thumbnails_dir() -> anyhow::Result<PathBuf> {
+    let cache_home = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home::home_dir()
+            .ok_or(anyhow!("No home directory found"))?
+            .join(".cache"),
+    };
+
+    Ok(cache_home.join("thumbnails"))
+}
@@ -0,0 +1,234 @@
+use crate::accordion_widget::LoadProgress;
+use crate::image_entry::{cached_texture, is_cached, show_cache_stats, take_save_failure};
+use crate::image_item::ImageItem;
+use crate::problems::{ProblemKind, Problems};
+use crate::thumbnail_scheduler::{Priority, ThumbnailScheduler};
+use gtk4 as gtk;
+use gtk4::prelude::{IsA, ListModelExtManual, ObjectExt, WidgetExt};
+use gtk4::{gdk, gio, glib};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+struct PendingJob {
+    id: u64,
+    progress: LoadProgress,
+}
+
+// Main loop side of the thumbnail scheduler. Tiles on screen are decoded first, then the tiles the grid
+// keeps around them, then the rest of every expanded section
+#[derive(Clone)]
+pub struct ThumbnailLoader {
+    scheduler: ThumbnailScheduler,
+    next_id: Rc<Cell<u64>>,
+    pending: Rc<RefCell<HashMap<ImageItem, PendingJob>>>,
+    bound: Rc<RefCell<HashMap<ImageItem, gtk::Widget>>>,
+    reprioritize_queued: Rc<Cell<bool>>,
+    problems: Problems,
+}
+
+impl ThumbnailLoader {
+    pub fn new(problems: Problems) -> Self {
+        Self {
+            scheduler: ThumbnailScheduler::default(),
+            next_id: Rc::new(Cell::new(0)),
+            pending: Rc::new(RefCell::new(HashMap::new())),
+            bound: Rc::new(RefCell::new(HashMap::new())),
+            reprioritize_queued: Rc::new(Cell::new(false)),
+            problems,
+        }
+    }
+
+    // Where tiles report files that could not be decoded or opened
+    pub fn problems(&self) -> &Problems {
+        &self.problems
+    }
+
+    pub fn bind(&self, item: &ImageItem, tile: &gtk::Widget, progress: &LoadProgress) {
+        self.bound.borrow_mut().insert(item.clone(), tile.clone());
+
+        // Thumbnails still in the memory cache are shown right away instead of going through a worker
+        if item.needs_texture()
+            && let Some(loaded_from) = item.loaded_from()
+            && let Some(texture) = cached_texture(&loaded_from, item.thumbnail_size())
+        {
+            item.set_loaded(Some(texture.as_ref()));
+        }
+
+        if !self.pending.borrow().contains_key(item) {
+            self.request(item, progress, Priority::Nearby);
+        }
+        // Bind happens before layout, so whether the tile is on screen is only known afterwards
+        self.queue_reprioritize();
+    }
+
+    pub fn unbind(&self, item: &ImageItem) {
+        self.bound.borrow_mut().remove(item);
+
+        // Off-screen tiles give their texture back, so the cache budget bounds what stays in memory
+        item.release_texture();
+
+        if let Some(job) = self.pending.borrow().get(item) {
+            self.scheduler.reprioritize(job.id, Priority::Background);
+        }
+    }
+
+    // Queues every tile of an expanded section behind the ones that are on screen
+    pub fn prefetch(&self, store: &gio::ListStore, progress: &LoadProgress) {
+        for item in store.iter::<ImageItem>().flatten() {
+            if !is_cached(&item.path(), item.thumbnail_size()) {
+                self.request(&item, progress, Priority::Background);
+            }
+        }
+    }
+
+    pub fn cancel(&self, progress: &LoadProgress) {
+        for job in self.pending.borrow().values() {
+            if job.progress.is_same(progress) {
+                self.scheduler.cancel(job.id);
+            }
+        }
+    }
+
+    // The file was rewritten on disk, so its tile is decoded again if it is shown
+    pub fn reload(&self, item: &ImageItem, progress: &LoadProgress) {
+        if item.loading() {
+            return;
+        }
+        item.set_failed(false);
+        item.set_texture(None::<&gdk::Texture>);
+        item.set_loaded_from(None);
+
+        if self.bound.borrow().contains_key(item) {
+            self.request(item, progress, Priority::Nearby);
+            self.queue_reprioritize();
+        }
+    }
+
+    // Scrolling and resizing only move tiles around, so the priorities are refreshed once per idle cycle
+    pub fn queue_reprioritize(&self) {
+        if self.reprioritize_queued.replace(true) {
+            return;
+        }
+
+        let loader = self.clone();
+        glib::idle_add_local_once(move || {
+            loader.reprioritize_queued.set(false);
+            loader.reprioritize();
+        });
+    }
+
+    // Only bound tiles can move on or off screen. Every other job was already put back to Background by
+    // `unbind` or `prefetch`, and a prefetched folder can have tens of thousands of them
+    fn reprioritize(&self) {
+        let pending = self.pending.borrow();
+
+        for (item, tile) in self.bound.borrow().iter() {
+            let Some(job) = pending.get(item) else {
+                continue;
+            };
+            let priority = if is_on_screen(tile) {
+                Priority::Visible
+            } else {
+                Priority::Nearby
+            };
+            self.scheduler.reprioritize(job.id, priority);
+        }
+    }
+
+    fn request(&self, item: &ImageItem, progress: &LoadProgress, priority: Priority) {
+        if !item.needs_texture() {
+            return;
+        }
+        item.set_loading(true);
+        progress.start();
+
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        let rx = self
+            .scheduler
+            .submit(id, item.path(), item.thumbnail_size(), priority);
+        self.pending.borrow_mut().insert(
+            item.clone(),
+            PendingJob {
+                id,
+                progress: progress.clone(),
+            },
+        );
+
+        let loader = self.clone();
+        let item = item.clone();
+        let progress = progress.clone();
+        glib::spawn_future_local(async move {
+            let result = rx.await;
+            loader.pending.borrow_mut().remove(&item);
+
+            match result {
+                Ok(Ok((texture, loaded_from))) => {
+                    item.set_loaded_from(Some(loaded_from));
+                    item.set_error("");
+                    item.set_loaded(Some(texture.as_ref()));
+                    loader.problems.resolve(ProblemKind::Decode, &item.path());
+                    // Prefetched thumbnails only warm the cache until their tile is bound
+                    if !loader.bound.borrow().contains_key(&item) {
+                        item.release_texture();
+                    }
+                }
+                Ok(Err(reason)) => {
+                    item.set_broken(&reason);
+                    let retry = glib::clone!(
+                        #[strong]
+                        loader,
+                        #[weak]
+                        item,
+                        #[strong]
+                        progress,
+                        move || loader.reload(&item, &progress)
+                    );
+                    loader
+                        .problems
+                        .report(ProblemKind::Decode, &item.path(), &reason, Some(retry));
+                }
+                // Cancelled before a worker picked it up, so it is requested again on the next bind
+                Err(_) => item.set_loading(false),
+            }
+            progress.finish();
+
+            if let Some(failure) = take_save_failure() {
+                loader.problems.notify(&failure);
+            }
+            if loader.pending.borrow().is_empty() {
+                show_cache_stats();
+            }
+        });
+    }
+}
+
+fn is_on_screen(tile: &gtk::Widget) -> bool {
+    if !tile.is_mapped() {
+        return false;
+    }
+
+    // Sections scroll inside the main window, so the tile has to be inside every scrolled window around it
+    let mut ancestor = tile.parent();
+    while let Some(widget) = ancestor {
+        if widget.is::<gtk::ScrolledWindow>() && !is_inside(tile, &widget) {
+            return false;
+        }
+        ancestor = widget.parent();
+    }
+
+    true
+}
+
+fn is_inside(widget: &gtk::Widget, viewport: &impl IsA<gtk::Widget>) -> bool {
+    let Some(bounds) = widget.compute_bounds(viewport) else {
+        return false;
+    };
+
+    bounds.x() + bounds.width() > 0.0
+        && bounds.y() + bounds.height() > 0.0
+        && bounds.x() < viewport.width() as f32
+        && bounds.y() < viewport.height() as f32
+}
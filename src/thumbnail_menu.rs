@@ -0,0 +1,208 @@
+use crate::APP_CONFIG;
+use crate::custom_action;
+use crate::open_with;
+use crate::problems::{ProblemKind, Problems};
+use gtk4 as gtk;
+use gtk4::prelude::{
+    ActionMapExt, AppInfoExt, CastNone, FileExt, IsA, PopoverExt, ToVariant, WidgetExt,
+};
+use gtk4::{gdk, gio, glib};
+
+// Context menu of a tile. The file is looked up when the menu opens, since tiles are recycled
+pub struct ThumbnailMenu {
+    popover: gtk::PopoverMenu,
+    actions: gio::SimpleActionGroup,
+    problems: Problems,
+}
+
+impl ThumbnailMenu {
+    pub fn new(parent: &impl IsA<gtk::Widget>, problems: &Problems) -> Self {
+        let popover = gtk::PopoverMenu::from_model(None::<&gio::MenuModel>);
+        popover.set_has_arrow(false);
+        popover.set_halign(gtk::Align::Start);
+        popover.set_parent(parent);
+
+        // The popover is not a regular child, so it has to be removed by hand
+        parent.connect_destroy(glib::clone!(
+            #[weak]
+            popover,
+            move |_| popover.unparent()
+        ));
+
+        let actions = gio::SimpleActionGroup::new();
+        parent.insert_action_group("tile", Some(&actions));
+
+        Self {
+            popover,
+            actions,
+            problems: problems.clone(),
+        }
+    }
+
+    // `targets` are the files custom actions run on: the selection if the tile is part of it, otherwise just `path`
+    pub fn popup(&self, path: &str, targets: &[String], x: f64, y: f64) {
+        let apps = open_with::apps_for(path);
+        let ext = open_with::extension(path);
+        let saved = ext
+            .as_ref()
+            .and_then(|ext| APP_CONFIG.read().ok()?.open_with.get(ext).cloned());
+
+        self.install_actions(path, &apps, ext.as_deref());
+
+        let menu = gio::Menu::new();
+
+        let open_section = gio::Menu::new();
+        for app in &apps {
+            let Some(id) = app.id() else {
+                continue;
+            };
+            let item = gio::MenuItem::new(Some(&app.display_name()), None);
+            item.set_action_and_target_value(Some("tile.open-with"), Some(&id.to_variant()));
+            open_section.append_item(&item);
+        }
+        open_section.append(Some("Other Application…"), Some("tile.open-other"));
+        menu.append_section(Some("Open With"), &open_section);
+
+        if let Some(ext) = &ext {
+            let default_menu = gio::Menu::new();
+            for app in &apps {
+                let Some(id) = app.id() else {
+                    continue;
+                };
+                let label = if saved.as_deref() == Some(id.as_str()) {
+                    format!("{} (current)", app.display_name())
+                } else {
+                    app.display_name().to_string()
+                };
+                let item = gio::MenuItem::new(Some(&label), None);
+                item.set_action_and_target_value(Some("tile.set-default"), Some(&id.to_variant()));
+                default_menu.append_item(&item);
+            }
+            let label = if saved.is_none() {
+                "Open command (current)"
+            } else {
+                "Open command"
+            };
+            let item = gio::MenuItem::new(Some(label), None);
+            item.set_action_and_target_value(Some("tile.set-default"), Some(&"".to_variant()));
+            default_menu.append_item(&item);
+
+            menu.append_submenu(Some(&format!("Always Open .{ext} With")), &default_menu);
+        }
+
+        let file_section = gio::Menu::new();
+        file_section.append(Some("Show in Folder"), Some("tile.reveal"));
+        file_section.append(Some("Move to Trash"), Some("tile.trash"));
+        menu.append_section(None, &file_section);
+
+        let custom_actions = APP_CONFIG
+            .read()
+            .map(|config| config.actions.clone())
+            .unwrap_or_default();
+        if !custom_actions.is_empty() {
+            let label = match targets.len() {
+                1 => "Actions".to_string(),
+                n => format!("Actions on {n} Images"),
+            };
+            menu.append_section(
+                Some(&label),
+                &custom_action::menu_section(&custom_actions, targets),
+            );
+        }
+
+        self.popover.set_menu_model(Some(&menu));
+        self.popover
+            .set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+        self.popover.popup();
+    }
+
+    fn install_actions(&self, path: &str, apps: &[gio::AppInfo], ext: Option<&str>) {
+        let reveal = gio::SimpleAction::new("reveal", None);
+        let path_inner = path.to_string();
+        let problems = self.problems.clone();
+        reveal.connect_activate(glib::clone!(
+            #[weak(rename_to = popover)]
+            self.popover,
+            move |_, _| {
+                let parent = popover.root().and_downcast::<gtk::Window>();
+                let problems = problems.clone();
+                gtk::FileLauncher::new(Some(&gio::File::for_path(&path_inner)))
+                    .open_containing_folder(
+                        parent.as_ref(),
+                        gio::Cancellable::NONE,
+                        move |result| {
+                            if let Err(e) = result {
+                                problems.notify(&format!("Failed to show the folder: {e}"));
+                            }
+                        },
+                    );
+            }
+        ));
+        self.actions.add_action(&reveal);
+
+        // The folder watcher removes the tile once the file is gone
+        let trash = gio::SimpleAction::new("trash", None);
+        let path_inner = path.to_string();
+        let problems = self.problems.clone();
+        trash.connect_activate(move |_, _| {
+            match gio::File::for_path(&path_inner).trash(gio::Cancellable::NONE) {
+                Ok(()) => {
+                    problems.resolve(ProblemKind::Decode, &path_inner);
+                    problems.resolve(ProblemKind::Open, &path_inner);
+                }
+                Err(e) => {
+                    problems.notify(&format!("Failed to move {path_inner} to the trash: {e}"))
+                }
+            }
+        });
+        self.actions.add_action(&trash);
+
+        let open_with = gio::SimpleAction::new("open-with", Some(glib::VariantTy::STRING));
+        let path_inner = path.to_string();
+        let apps_inner = apps.to_vec();
+        let problems = self.problems.clone();
+        open_with.connect_activate(move |_, parameter| {
+            let Some(id) = parameter.and_then(|parameter| parameter.str()) else {
+                return;
+            };
+            if let Some(app) = apps_inner
+                .iter()
+                .find(|app| app.id().is_some_and(|app_id| app_id == id))
+            {
+                open_with::launch_or_report(app, &path_inner, &problems);
+            }
+        });
+        self.actions.add_action(&open_with);
+
+        let open_other = gio::SimpleAction::new("open-other", None);
+        let path_inner = path.to_string();
+        let problems = self.problems.clone();
+        open_other.connect_activate(glib::clone!(
+            #[weak(rename_to = popover)]
+            self.popover,
+            move |_, _| {
+                let parent = popover.root().and_downcast::<gtk::Window>();
+                open_with::choose_app(parent.as_ref(), &path_inner, &problems);
+            }
+        ));
+        self.actions.add_action(&open_other);
+
+        let set_default = gio::SimpleAction::new("set-default", Some(glib::VariantTy::STRING));
+        set_default.set_enabled(ext.is_some());
+        let ext = ext.unwrap_or_default().to_string();
+        let apps = apps.to_vec();
+        let problems = self.problems.clone();
+        set_default.connect_activate(move |_, parameter| {
+            let Some(id) = parameter.and_then(|parameter| parameter.str()) else {
+                return;
+            };
+            let app = apps
+                .iter()
+                .find(|app| app.id().is_some_and(|app_id| app_id == id));
+            if let Err(e) = open_with::save_default(&ext, app) {
+                problems.notify(&format!("Failed to save the default application: {e}"));
+            }
+        });
+        self.actions.add_action(&set_default);
+    }
+}
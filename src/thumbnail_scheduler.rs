@@ -0,0 +1,127 @@
+use crate::image_entry::ImageEntry;
+use crate::texture_cache::CacheKey;
+use futures_channel::oneshot;
+use gtk4::gdk::Texture;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Visible,
+    Nearby,
+    Background,
+}
+
+// The texture comes with the file it was made from. The error is kept as text, so it can be shown next to the file
+pub type JobResult = Result<(Arc<Texture>, CacheKey), String>;
+
+struct Job {
+    path: String,
+    thumbnail_size: u32,
+    tx: oneshot::Sender<JobResult>,
+}
+
+// Jobs are ordered by priority first and by submission order within the same priority
+#[derive(Default)]
+struct JobQueue {
+    jobs: BTreeMap<(Priority, u64), Job>,
+    priorities: HashMap<u64, Priority>,
+}
+
+#[derive(Clone, Default)]
+pub struct ThumbnailScheduler {
+    queue: Arc<Mutex<JobQueue>>,
+}
+
+impl ThumbnailScheduler {
+    pub fn submit(
+        &self,
+        id: u64,
+        path: String,
+        thumbnail_size: u32,
+        priority: Priority,
+    ) -> oneshot::Receiver<JobResult> {
+        let (tx, rx) = oneshot::channel();
+
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.jobs.insert(
+                (priority, id),
+                Job {
+                    path,
+                    thumbnail_size,
+                    tx,
+                },
+            );
+            queue.priorities.insert(id, priority);
+        }
+
+        // Every submission adds one worker task to the rayon pool, which then runs whichever job is most urgent
+        let queue = self.queue.clone();
+        rayon::spawn(move || run_next(&queue));
+
+        rx
+    }
+
+    pub fn reprioritize(&self, id: u64, priority: Priority) {
+        let Ok(mut guard) = self.queue.lock() else {
+            return;
+        };
+        let queue = &mut *guard;
+
+        let Some(current) = queue.priorities.get_mut(&id) else {
+            return;
+        };
+        if *current == priority {
+            return;
+        }
+
+        let previous = std::mem::replace(current, priority);
+        if let Some(job) = queue.jobs.remove(&(previous, id)) {
+            queue.jobs.insert((priority, id), job);
+        }
+    }
+
+    // Dropping a queued job closes its channel, which tells the waiting tile that nothing was loaded
+    pub fn cancel(&self, id: u64) {
+        let Ok(mut guard) = self.queue.lock() else {
+            return;
+        };
+        let queue = &mut *guard;
+
+        if let Some(priority) = queue.priorities.remove(&id) {
+            queue.jobs.remove(&(priority, id));
+        }
+    }
+}
+
+fn run_next(queue: &Mutex<JobQueue>) {
+    let job = {
+        let Ok(mut guard) = queue.lock() else {
+            return;
+        };
+        let queue = &mut *guard;
+
+        let Some(((_, id), job)) = queue.jobs.pop_first() else {
+            return;
+        };
+        queue.priorities.remove(&id);
+        job
+    };
+
+    if job.tx.is_canceled() {
+        return;
+    }
+
+    let mut image_entry = ImageEntry {
+        image_path: job.path,
+        image: None,
+    };
+    let result = match image_entry.load_image(job.thumbnail_size) {
+        Ok(key) => image_entry
+            .image
+            .map(|texture| (texture, key))
+            .ok_or_else(|| "No image was decoded".to_string()),
+        Err(e) => Err(format!("{e:#}")),
+    };
+    let _ = job.tx.send(result);
+}